@@ -0,0 +1,100 @@
+use bevy::math::{Quat, Vec3};
+use bevy::reflect::{
+    serde::{TypedReflectDeserializer, TypedReflectSerializer},
+    GetTypeRegistration, TypeRegistry,
+};
+use bevy::transform::components::Transform;
+use bevy_rome::{codec::Codec, entity::EntityId, queue::Envelope, SetFieldMsg};
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+const CODECS: [(&str, Codec); 2] = [("ron", Codec::Ron), ("binary", Codec::Binary)];
+
+fn registry() -> TypeRegistry {
+    let mut registry = TypeRegistry::default();
+    registry.register::<Transform>();
+    registry.register::<Vec3>();
+    registry.register::<Quat>();
+    registry
+}
+
+fn transform() -> Transform {
+    Transform {
+        translation: Vec3::new(1.5, -20.25, 300.125),
+        rotation: Quat::from_rotation_y(0.7),
+        scale: Vec3::ONE,
+    }
+}
+
+/// Envelope of a single [`SetFieldMsg`] setting the translation of a
+/// [`Transform`], the typical message sent while dragging an entity. The value
+/// of the field is serialized with the same codec as the envelope.
+fn set_field_msg(c: &mut Criterion) {
+    let registry = registry();
+    let mut group = c.benchmark_group("set_field_msg");
+    for (name, codec) in CODECS {
+        let translation = transform().translation;
+        let msg =
+            SetFieldMsg::of::<Transform>(".translation", &translation, &registry, codec).unwrap();
+        let payload = codec.encode(&msg).unwrap();
+        let envelope = Envelope::new("bevy_rome::SetFieldMsg", EntityId::new(), 1234, payload);
+        let bytes = codec.encode(&envelope).unwrap();
+        group.throughput(Throughput::Bytes(bytes.len() as u64));
+        group.bench_with_input(
+            BenchmarkId::new("encode", name),
+            &envelope,
+            |b, envelope| {
+                b.iter(|| {
+                    let payload = codec.encode(black_box(&msg)).unwrap();
+                    let envelope =
+                        Envelope::new(envelope.kind(), envelope.target(), envelope.seq(), payload);
+                    codec.encode(&envelope).unwrap()
+                })
+            },
+        );
+        group.bench_with_input(BenchmarkId::new("decode", name), &bytes, |b, bytes| {
+            b.iter(|| {
+                let envelope: Envelope = codec.decode(black_box(bytes)).unwrap();
+                codec.decode::<SetFieldMsg>(envelope.payload()).unwrap()
+            })
+        });
+    }
+    group.finish();
+}
+
+/// Reflected [`Transform`], as recorded in the diffs of a whole component.
+fn transform_reflect(c: &mut Criterion) {
+    let registry = registry();
+    let registration = registry
+        .get(Transform::get_type_registration().type_id())
+        .unwrap();
+
+    let mut group = c.benchmark_group("transform");
+    for (name, codec) in CODECS {
+        let value = transform();
+        let bytes = codec
+            .encode(&TypedReflectSerializer::new(&value, &registry))
+            .unwrap();
+        group.throughput(Throughput::Bytes(bytes.len() as u64));
+        group.bench_with_input(BenchmarkId::new("encode", name), &value, |b, value| {
+            b.iter(|| {
+                codec
+                    .encode(&TypedReflectSerializer::new(black_box(value), &registry))
+                    .unwrap()
+            })
+        });
+        group.bench_with_input(BenchmarkId::new("decode", name), &bytes, |b, bytes| {
+            b.iter(|| {
+                codec
+                    .decode_seed(
+                        TypedReflectDeserializer::new(registration, &registry),
+                        black_box(bytes),
+                    )
+                    .unwrap()
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, set_field_msg, transform_reflect);
+criterion_main!(benches);
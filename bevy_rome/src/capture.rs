@@ -0,0 +1,396 @@
+use bevy::app::{App, Plugin, PostUpdate};
+use bevy::ecs::{
+    component::Component,
+    entity::{Entity, EntityHashMap},
+    event::Event,
+    query::{Changed, QueryState},
+    reflect::AppTypeRegistry,
+    removal_detection::RemovedComponents,
+    schedule::{InternedScheduleLabel, IntoSystemConfigs, ScheduleLabel, SystemSet},
+    system::{Resource, SystemState},
+    world::{Mut, World},
+};
+use bevy::log::warn;
+use bevy::reflect::{FromType, Reflect, TypePath, TypeRegistry};
+use bevy::utils::{default, HashMap};
+use std::any::{Any, TypeId};
+
+use super::codec::Codec;
+use super::diff::{Diff, DiffContent, DiffData, DiffTarget};
+use super::error::Error;
+
+/// Type data enabling the automatic capture of diffs for a component type.
+///
+/// Only component types registered with this type data are captured by the
+/// [`DiffCapturePlugin`]. Register it either with `#[reflect(DiffCapture)]`
+/// on the component type, or with
+/// `app.register_type_data::<T, ReflectDiffCapture>()`.
+#[derive(Clone)]
+pub struct ReflectDiffCapture {
+    capture:
+        fn(&mut World, &mut DiffCaptureState, &TypeRegistry, &mut Vec<Diff>) -> Result<(), Error>,
+}
+
+impl<T: Component + Reflect + TypePath> FromType<T> for ReflectDiffCapture {
+    fn from_type() -> Self {
+        Self {
+            capture: capture_component::<T>,
+        }
+    }
+}
+
+/// Event emitted for each diff captured by the [`DiffCapturePlugin`].
+#[derive(Debug, Clone, Event)]
+pub struct DiffEvent(pub Diff);
+
+/// System set of the diff capture system.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, SystemSet)]
+pub struct DiffCaptureSet;
+
+/// Capture state of a single component type.
+struct ComponentCapture {
+    /// Type-erased `QueryState<(Entity, &T), Changed<T>>`, cached to preserve
+    /// its archetype matching across frames.
+    query: Box<dyn Any + Send + Sync>,
+    /// Type-erased `SystemState<RemovedComponents<T>>`, reading the removals
+    /// since the last capture.
+    removed: Box<dyn Any + Send + Sync>,
+    /// Copy of the value of each component at the time of the last capture.
+    shadows: EntityHashMap<Box<dyn Reflect>>,
+}
+
+/// Internal state of the [`DiffCapturePlugin`].
+#[derive(Resource)]
+pub struct DiffCaptureState {
+    two_way: bool,
+    codec: Codec,
+    components: HashMap<TypeId, ComponentCapture>,
+}
+
+impl DiffCaptureState {
+    pub(crate) fn new(two_way: bool, codec: Codec) -> Self {
+        Self {
+            two_way,
+            codec,
+            components: default(),
+        }
+    }
+}
+
+/// Plugin capturing diffs of changed components.
+///
+/// Each time the capture system runs, it detects all components changed since
+/// its previous run, for all component types registered with the
+/// [`ReflectDiffCapture`] type data. For each component, it compares the new
+/// value with a copy (_shadow_) of its value at the time of the previous
+/// capture, and emits a [`DiffEvent`] with the resulting [`Diff`] if not
+/// empty. Newly inserted components emit a [`DiffContent::Insert`] diff, and
+/// removed ones (including from despawned entities) a
+/// [`DiffContent::Remove`] one.
+///
+/// The capture system runs in the [`DiffCaptureSet`] system set, by default
+/// in the [`PostUpdate`] schedule. The schedule doesn't need to run every
+/// frame; removals older than two frames are found by checking all the
+/// captured entities, which is slower.
+pub struct DiffCapturePlugin {
+    /// Schedule the capture system runs in.
+    pub schedule: InternedScheduleLabel,
+    /// Capture two-way diffs, which can be inverted.
+    pub two_way: bool,
+    /// Format of the values recorded in the captured diffs.
+    pub codec: Codec,
+}
+
+impl Default for DiffCapturePlugin {
+    fn default() -> Self {
+        Self {
+            schedule: PostUpdate.intern(),
+            two_way: true,
+            codec: Codec::Ron,
+        }
+    }
+}
+
+impl DiffCapturePlugin {
+    /// Create a plugin capturing diffs in the given schedule.
+    pub fn new(schedule: impl ScheduleLabel) -> Self {
+        Self {
+            schedule: schedule.intern(),
+            ..Default::default()
+        }
+    }
+}
+
+impl Plugin for DiffCapturePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<DiffEvent>()
+            .insert_resource(DiffCaptureState::new(self.two_way, self.codec))
+            .add_systems(self.schedule, capture_diffs.in_set(DiffCaptureSet));
+    }
+}
+
+/// Capture the diffs of all changed components, and send them as
+/// [`DiffEvent`]s.
+pub fn capture_diffs(world: &mut World) {
+    let registry = world.resource::<AppTypeRegistry>().clone();
+    let registry = registry.read();
+    let mut diffs = vec![];
+    world.resource_scope(|world, mut state: Mut<DiffCaptureState>| {
+        for registration in registry.iter() {
+            let Some(capture) = registration.data::<ReflectDiffCapture>() else {
+                continue;
+            };
+            if let Err(err) = (capture.capture)(world, &mut state, &registry, &mut diffs) {
+                warn!(
+                    "Failed to capture diffs for component {}: {:?}",
+                    registration.type_info().type_path(),
+                    err
+                );
+            }
+        }
+    });
+    world.send_event_batch(diffs.into_iter().map(DiffEvent));
+}
+
+fn capture_component<T: Component + Reflect + TypePath>(
+    world: &mut World,
+    state: &mut DiffCaptureState,
+    registry: &TypeRegistry,
+    diffs: &mut Vec<Diff>,
+) -> Result<(), Error> {
+    let two_way = state.two_way;
+    let codec = state.codec;
+    let capture = state
+        .components
+        .entry(TypeId::of::<T>())
+        .or_insert_with(|| ComponentCapture {
+            query: Box::new(world.query_filtered::<(Entity, &T), Changed<T>>()),
+            removed: Box::new(SystemState::<RemovedComponents<T>>::new(world)),
+            shadows: default(),
+        });
+    let query = capture
+        .query
+        .downcast_mut::<QueryState<(Entity, &T), Changed<T>>>()
+        .unwrap();
+
+    // Changed and inserted components
+    for (entity, value) in query.iter(world) {
+        let target = DiffTarget::new(entity, T::type_path());
+        if let Some(shadow) = capture.shadows.get_mut(&entity) {
+            let diff = Diff::make_impl(shadow.as_ref(), value, registry, two_way, codec)?;
+            if !diff.is_empty() {
+                diffs.push(diff.with_target(target));
+            }
+            *shadow = value.clone_value();
+        } else {
+            let data = DiffData::new(String::new(), value, registry, codec)?;
+            diffs.push(Diff::new(target, vec![DiffContent::Insert(data)]));
+            capture.shadows.insert(entity, value.clone_value());
+        }
+    }
+
+    // Removed components, unless re-inserted since. Removals are only kept for
+    // two frames; if some were missed because the capture didn't run since,
+    // check all the entities with a shadow instead.
+    let mut removed_components = capture
+        .removed
+        .downcast_mut::<SystemState<RemovedComponents<T>>>()
+        .unwrap()
+        .get(world);
+    let missed = removed_components
+        .events()
+        .is_some_and(|events| removed_components.reader().missed_events(events) > 0);
+    let mut removed: Vec<Entity> = removed_components.read().collect();
+    if missed {
+        removed.extend(capture.shadows.keys().copied());
+    }
+    removed.retain(|entity| world.get::<T>(*entity).is_none());
+    for entity in removed {
+        let Some(shadow) = capture.shadows.remove(&entity) else {
+            continue;
+        };
+        let data = if two_way {
+            Some(DiffData::new(
+                String::new(),
+                shadow.as_ref(),
+                registry,
+                codec,
+            )?)
+        } else {
+            None
+        };
+        diffs.push(Diff::new(
+            DiffTarget::new(entity, T::type_path()),
+            vec![DiffContent::Remove(data)],
+        ));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::app::Update;
+    use bevy::ecs::{event::Events, reflect::ReflectComponent};
+
+    #[derive(Debug, Clone, PartialEq, Component, Reflect)]
+    #[reflect(Component, DiffCapture)]
+    struct C {
+        f: f32,
+        i: i32,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Component, Reflect)]
+    #[reflect(Component)]
+    struct NotCaptured(u32);
+
+    fn app(plugin: DiffCapturePlugin) -> App {
+        let mut app = App::new();
+        app.add_plugins(plugin)
+            .register_type::<C>()
+            .register_type::<NotCaptured>();
+        app
+    }
+
+    fn update(app: &mut App) -> Vec<Diff> {
+        app.update();
+        app.world
+            .resource_mut::<Events<DiffEvent>>()
+            .drain()
+            .map(|event| event.0)
+            .collect()
+    }
+
+    #[test]
+    fn capture() {
+        let mut app = app(DiffCapturePlugin::default());
+        let entity = app.world.spawn((C { f: 1., i: 2 }, NotCaptured(3))).id();
+        let target = DiffTarget::of::<C>(entity);
+
+        // Insert
+        let diffs = update(&mut app);
+        assert_eq!(diffs.len(), 1);
+        assert_eq!(diffs[0].target(), Some(&target));
+        assert!(matches!(diffs[0].content(), [DiffContent::Insert(_)]));
+
+        // No change
+        assert!(update(&mut app).is_empty());
+        app.world.get_mut::<NotCaptured>(entity).unwrap().0 = 4;
+        assert!(update(&mut app).is_empty());
+
+        // Change detected, but value unchanged
+        app.world.get_mut::<C>(entity).unwrap().f = 1.;
+        assert!(update(&mut app).is_empty());
+
+        // Change
+        app.world.get_mut::<C>(entity).unwrap().i = 5;
+        let diffs = update(&mut app);
+        assert_eq!(diffs.len(), 1);
+        assert_eq!(diffs[0].target(), Some(&target));
+        let [DiffContent::Dual(old, new)] = diffs[0].content() else {
+            panic!("Expected a single two-way change.");
+        };
+        assert_eq!(old.path(), ".i");
+        assert_eq!(old.data(), b"2");
+        assert_eq!(new.data(), b"5");
+
+        // Remove
+        app.world.entity_mut(entity).remove::<C>();
+        let diffs = update(&mut app);
+        assert_eq!(diffs.len(), 1);
+        assert!(matches!(diffs[0].content(), [DiffContent::Remove(Some(_))]));
+
+        // Removed then re-inserted
+        app.world.entity_mut(entity).insert(C { f: 0., i: 0 });
+        assert_eq!(update(&mut app).len(), 1);
+        app.world.entity_mut(entity).remove::<C>();
+        app.world.entity_mut(entity).insert(C { f: 0., i: 1 });
+        let diffs = update(&mut app);
+        assert_eq!(diffs.len(), 1);
+        assert!(matches!(diffs[0].content(), [DiffContent::Dual(_, _)]));
+
+        // Despawn
+        app.world.despawn(entity);
+        let diffs = update(&mut app);
+        assert_eq!(diffs.len(), 1);
+        assert!(matches!(diffs[0].content(), [DiffContent::Remove(Some(_))]));
+    }
+
+    #[test]
+    fn capture_schedule() {
+        let mut app = app(DiffCapturePlugin {
+            schedule: Update.intern(),
+            two_way: false,
+            ..default()
+        });
+        let entity = app.world.spawn(C { f: 1., i: 2 }).id();
+        assert_eq!(update(&mut app).len(), 1);
+
+        // Modify after capture in the same frame; captured on next frame
+        app.add_systems(
+            Update,
+            (|mut query: bevy::ecs::system::Query<&mut C>| {
+                for mut c in &mut query {
+                    c.f += 1.;
+                }
+            })
+            .after(DiffCaptureSet),
+        );
+        assert!(update(&mut app).is_empty());
+        let diffs = update(&mut app);
+        assert_eq!(diffs.len(), 1);
+        assert_eq!(diffs[0].target(), Some(&DiffTarget::of::<C>(entity)));
+        assert!(!diffs[0].is_two_way());
+    }
+
+    #[test]
+    fn capture_rare_schedule() {
+        #[derive(Debug, Clone, PartialEq, Eq, Hash, ScheduleLabel)]
+        struct Rare;
+
+        let mut app = app(DiffCapturePlugin::new(Rare));
+        let entity = app.world.spawn(C { f: 1., i: 2 }).id();
+        app.world.run_schedule(Rare);
+        assert_eq!(update(&mut app).len(), 1);
+
+        // Removal events dropped before the next capture
+        app.world.entity_mut(entity).remove::<C>();
+        for _ in 0..3 {
+            assert!(update(&mut app).is_empty());
+        }
+        app.world.run_schedule(Rare);
+        let diffs = update(&mut app);
+        assert_eq!(diffs.len(), 1);
+        assert_eq!(diffs[0].target(), Some(&DiffTarget::of::<C>(entity)));
+        assert!(matches!(diffs[0].content(), [DiffContent::Remove(Some(_))]));
+    }
+
+    #[test]
+    fn capture_binary() {
+        let mut app = app(DiffCapturePlugin {
+            codec: Codec::Binary,
+            ..default()
+        });
+        let entity = app.world.spawn(C { f: 1., i: 2 }).id();
+        let mut copy = World::new();
+        let copy_entity = copy.spawn(C { f: 1., i: 2 }).id();
+        let mut entity_map = EntityHashMap::default();
+        entity_map.insert(entity, copy_entity);
+        update(&mut app);
+
+        app.world.get_mut::<C>(entity).unwrap().i = 5;
+        let diffs = update(&mut app);
+        assert_eq!(diffs.len(), 1);
+        let [DiffContent::Dual(old, new)] = diffs[0].content() else {
+            panic!("Expected a single two-way change.");
+        };
+        assert_eq!(old.codec(), Codec::Binary);
+        assert_eq!(new.codec(), Codec::Binary);
+        let registry = app.world.resource::<AppTypeRegistry>().read();
+        diffs[0]
+            .apply_world_mapped(&mut copy, &registry, &mut entity_map)
+            .unwrap();
+        assert_eq!(*copy.get::<C>(copy_entity).unwrap(), C { f: 1., i: 5 });
+    }
+}
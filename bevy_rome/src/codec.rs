@@ -0,0 +1,177 @@
+use bincode::Options;
+use serde::{
+    de::{DeserializeOwned, DeserializeSeed},
+    Deserialize, Serialize,
+};
+
+use super::error::Error;
+
+/// Serialization format of messages and diffs.
+///
+/// [`Ron`] produces human-readable text, convenient for debugging and storage.
+/// [`Binary`] produces a compact binary encoding, faster to produce and parse,
+/// and better suited to live editing where messages are exchanged each frame.
+/// Both formats support the same set of messages and diffs.
+///
+/// [`Ron`]: Codec::Ron
+/// [`Binary`]: Codec::Binary
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Codec {
+    /// Text format, using [RON](https://github.com/ron-rs/ron).
+    #[default]
+    Ron,
+    /// Binary format, using [bincode](https://github.com/bincode-org/bincode)
+    /// with variable-length integer encoding.
+    Binary,
+}
+
+impl Codec {
+    /// Serialize a value.
+    pub fn encode<T: Serialize + ?Sized>(&self, value: &T) -> Result<Vec<u8>, Error> {
+        match self {
+            Codec::Ron => Ok(ron::to_string(value)?.into_bytes()),
+            Codec::Binary => {
+                // Unlike `serialize()`, this avoids a first pass to compute the
+                // serialized size, which is costly for reflected values.
+                let mut bytes = Vec::with_capacity(BINARY_CAPACITY);
+                binary_options().serialize_into(&mut bytes, value)?;
+                Ok(bytes)
+            }
+        }
+    }
+
+    /// Deserialize a value.
+    ///
+    /// The entire input must be consumed; trailing bytes produce an error.
+    pub fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, Error> {
+        self.decode_seed(std::marker::PhantomData::<T>, bytes)
+    }
+
+    /// Deserialize a value with a stateful deserializer, like a
+    /// [`TypedReflectDeserializer`].
+    ///
+    /// The entire input must be consumed; trailing bytes produce an error.
+    ///
+    /// [`TypedReflectDeserializer`]: bevy::reflect::serde::TypedReflectDeserializer
+    pub fn decode_seed<'de, S: DeserializeSeed<'de>>(
+        &self,
+        seed: S,
+        bytes: &'de [u8],
+    ) -> Result<S::Value, Error> {
+        match self {
+            Codec::Ron => {
+                let mut deserializer = ron::Deserializer::from_bytes(bytes)?;
+                // Record the position of the error in the input
+                let value = seed
+                    .deserialize(&mut deserializer)
+                    .map_err(|err| deserializer.span_error(err))?;
+                deserializer
+                    .end()
+                    .map_err(|err| deserializer.span_error(err))?;
+                Ok(value)
+            }
+            Codec::Binary => Ok(binary_options().deserialize_seed(seed, bytes)?),
+        }
+    }
+}
+
+/// Initial capacity of the buffer of binary-serialized values, large enough
+/// for most messages to avoid reallocations.
+const BINARY_CAPACITY: usize = 128;
+
+fn binary_options() -> impl Options {
+    bincode::DefaultOptions::new()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::reflect::{
+        serde::{TypedReflectDeserializer, TypedReflectSerializer},
+        FromReflect, GetTypeRegistration, Reflect, TypeRegistry,
+    };
+    use bevy::utils::HashMap;
+
+    #[derive(Debug, Clone, PartialEq, Reflect, Serialize, Deserialize)]
+    enum E {
+        A,
+        B(u32, f32),
+        C { s: String },
+    }
+
+    #[derive(Debug, Clone, PartialEq, Reflect, Serialize, Deserialize)]
+    struct S {
+        f: f32,
+        e: E,
+        o: Option<u8>,
+        v: Vec<i32>,
+        m: HashMap<String, u32>,
+    }
+
+    fn value() -> S {
+        S {
+            f: 1.5,
+            e: E::C {
+                s: "abc".to_string(),
+            },
+            o: Some(3),
+            v: vec![1, -2, 3],
+            m: [("x".to_string(), 4)].into_iter().collect(),
+        }
+    }
+
+    #[test]
+    fn roundtrip() {
+        for codec in [Codec::Ron, Codec::Binary] {
+            for value in [
+                value(),
+                S {
+                    e: E::A,
+                    o: None,
+                    ..value()
+                },
+            ] {
+                let bytes = codec.encode(&value).unwrap();
+                assert_eq!(codec.decode::<S>(&bytes).unwrap(), value);
+
+                // Trailing bytes
+                let mut bytes = bytes;
+                bytes.push(b'0');
+                assert!(codec.decode::<S>(&bytes).is_err());
+            }
+        }
+
+        let value = S {
+            e: E::B(7, 0.5),
+            ..value()
+        };
+        let ron = Codec::Ron.encode(&value).unwrap();
+        let binary = Codec::Binary.encode(&value).unwrap();
+        assert!(binary.len() < ron.len());
+    }
+
+    #[test]
+    fn reflect() {
+        let mut registry = TypeRegistry::default();
+        registry.register::<S>();
+        registry.register::<E>();
+        registry.register::<Option<u8>>();
+        registry.register::<Vec<i32>>();
+        registry.register::<HashMap<String, u32>>();
+        let registration = registry.get(S::get_type_registration().type_id()).unwrap();
+
+        for codec in [Codec::Ron, Codec::Binary] {
+            let value = value();
+            let bytes = codec
+                .encode(&TypedReflectSerializer::new(&value, &registry))
+                .unwrap();
+            let reflected = codec
+                .decode_seed(
+                    TypedReflectDeserializer::new(registration, &registry),
+                    &bytes,
+                )
+                .unwrap();
+            assert_eq!(S::from_reflect(reflected.as_ref()).unwrap(), value);
+        }
+    }
+}
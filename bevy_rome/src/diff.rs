@@ -1,103 +1,1410 @@
 use bevy::ecs::{
-    component::{Component, ComponentId},
-    entity::Entity,
+    component::Component,
+    entity::{Entity, EntityHashMap},
+    reflect::ReflectComponent,
     world::{Mut, World},
 };
-use bevy::math::Vec3;
-use bevy::reflect::{Reflect, ReflectRef};
-use bevy::transform::components::Transform;
-use serde::{Deserialize, Serialize, Serializer};
-use std::any::{Any, TypeId};
-use std::io::prelude::*;
-use std::net::{TcpListener, TcpStream};
+use bevy::reflect::{
+    serde::{TypedReflectDeserializer, TypedReflectSerializer},
+    Enum, GetPath, Reflect, ReflectMut, ReflectPathError, ReflectRef, Struct, TypePath,
+    TypeRegistration, TypeRegistry,
+};
+use serde::{Deserialize, Serialize};
 
+use super::codec::Codec;
 use super::error::Error;
+use super::FieldPath;
 
-#[derive(Serialize, Deserialize)]
-struct DiffTarget {
+/// Target of a diff, a component on an entity, or an entity itself.
+///
+/// The component is identified by its type path, which unlike its
+/// [`ComponentId`] is stable across processes and executions.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DiffTarget {
     entity: Entity,
-    component: usize, // ComponentId,
+    component: Option<String>,
 }
 
 impl DiffTarget {
-    pub fn resolve<'w, T: Component>(&self, world: &'w World) -> Option<&'w T> {
-        if let Some(entity) = world.get_entity(self.entity) {
-            // TODO - Validate self.component!
-            entity.get::<T>()
+    /// Create a new target from an entity and the type path of a component.
+    pub fn new(entity: Entity, component: impl Into<String>) -> Self {
+        Self {
+            entity,
+            component: Some(component.into()),
+        }
+    }
+
+    /// Create a new target for an entity itself, without any component.
+    ///
+    /// Entity targets are used by diffs spawning or despawning an entity.
+    pub fn from_entity(entity: Entity) -> Self {
+        Self {
+            entity,
+            component: None,
+        }
+    }
+
+    /// Create a new target for the component `T` of an entity.
+    pub fn of<T: Component + TypePath>(entity: Entity) -> Self {
+        Self::new(entity, T::type_path())
+    }
+
+    /// The target entity.
+    pub fn entity(&self) -> Entity {
+        self.entity
+    }
+
+    /// The type path of the target component, if any.
+    pub fn component(&self) -> Option<&str> {
+        self.component.as_deref()
+    }
+
+    /// Resolve the target component on the given world.
+    ///
+    /// Fails if `T` is not the target component type, if the entity doesn't
+    /// exist, or if it doesn't have the component.
+    pub fn resolve<'w, T: Component + TypePath>(&self, world: &'w World) -> Result<&'w T, Error> {
+        self.validate::<T>()?;
+        world
+            .get_entity(self.entity)
+            .ok_or(Error::EntityNotFound(self.entity))?
+            .get::<T>()
+            .ok_or_else(|| self.component_not_found())
+    }
+
+    /// Resolve the target component on the given world for mutation.
+    ///
+    /// Fails if `T` is not the target component type, if the entity doesn't
+    /// exist, or if it doesn't have the component.
+    pub fn resolve_mut<'w, T: Component + TypePath>(
+        &self,
+        world: &'w mut World,
+    ) -> Result<Mut<'w, T>, Error> {
+        self.validate::<T>()?;
+        if world.get_entity(self.entity).is_none() {
+            return Err(Error::EntityNotFound(self.entity));
+        }
+        world
+            .get_mut::<T>(self.entity)
+            .ok_or_else(|| self.component_not_found())
+    }
+
+    /// Resolve the target component on the given world as a reflected value.
+    ///
+    /// The component type must be registered in the `registry` with its
+    /// [`ReflectComponent`] type data.
+    pub fn resolve_reflect<'w>(
+        &self,
+        world: &'w World,
+        registry: &TypeRegistry,
+    ) -> Result<&'w dyn Reflect, Error> {
+        let reflect_component = self.reflect_component(registry)?;
+        let entity = world
+            .get_entity(self.entity)
+            .ok_or(Error::EntityNotFound(self.entity))?;
+        reflect_component
+            .reflect(entity)
+            .ok_or_else(|| self.component_not_found())
+    }
+
+    /// Resolve the target component on the given world as a mutable reflected
+    /// value.
+    ///
+    /// The component type must be registered in the `registry` with its
+    /// [`ReflectComponent`] type data.
+    pub fn resolve_reflect_mut<'w>(
+        &self,
+        world: &'w mut World,
+        registry: &TypeRegistry,
+    ) -> Result<Mut<'w, dyn Reflect>, Error> {
+        let reflect_component = self.reflect_component(registry)?;
+        let entity = world
+            .as_unsafe_world_cell()
+            .get_entity(self.entity)
+            .ok_or(Error::EntityNotFound(self.entity))?;
+        // SAFETY: The world is borrowed mutably for the lifetime of the returned
+        // reference, and this is the only access made to it.
+        unsafe { reflect_component.reflect_unchecked_mut(entity) }
+            .ok_or_else(|| self.component_not_found())
+    }
+
+    fn validate<T: Component + TypePath>(&self) -> Result<(), Error> {
+        if self.component.as_deref() == Some(T::type_path()) {
+            Ok(())
         } else {
-            None
+            Err(Error::ComponentMismatch {
+                expected: T::type_path().to_string(),
+                actual: self.component.clone(),
+            })
         }
     }
 
-    // pub fn resolve_mut<'w, T: Component>(&self, world: &'w mut World) ->
-    // Option<Mut<'w, T>> {     if let Some(mut entity) =
-    // world.get_entity_mut(self.entity) {         // TODO - Validate
-    // self.component!         entity.get_mut::<T>()
-    //     } else {
-    //         None
-    //     }
-    // }
+    fn registration<'r>(&self, registry: &'r TypeRegistry) -> Result<&'r TypeRegistration, Error> {
+        let component = self.component.as_ref().ok_or(Error::NoTarget)?;
+        registry
+            .get_with_type_path(component)
+            .ok_or_else(|| Error::UnregisteredType(component.clone()))
+    }
+
+    fn reflect_component<'r>(
+        &self,
+        registry: &'r TypeRegistry,
+    ) -> Result<&'r ReflectComponent, Error> {
+        let registration = self.registration(registry)?;
+        registration.data::<ReflectComponent>().ok_or_else(|| {
+            Error::UnregisteredType(registration.type_info().type_path().to_string())
+        })
+    }
+
+    fn component_not_found(&self) -> Error {
+        Error::ComponentNotFound {
+            entity: self.entity,
+            component: self.component.clone().unwrap_or_default(),
+        }
+    }
+
+    /// Copy of this target for the same component of another entity.
+    pub fn with_entity(&self, entity: Entity) -> DiffTarget {
+        DiffTarget {
+            entity,
+            component: self.component.clone(),
+        }
+    }
+
+    /// Copy of this target with the entity replaced by its mapped value, if
+    /// any.
+    fn mapped(&self, entity_map: &EntityHashMap<Entity>) -> DiffTarget {
+        self.with_entity(entity_map.get(&self.entity).copied().unwrap_or(self.entity))
+    }
 }
 
-#[derive(Serialize, Deserialize)]
-struct DiffData {
+/// Serialized value of a single changed leaf of a `Reflect` object.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DiffData {
+    /// Reflect path of the value, relative to the root object. An empty path
+    /// designates the root object itself.
     path: String,
+    /// Serialized value, as produced by a [`TypedReflectSerializer`].
     data: Vec<u8>,
+    /// Format of the serialized value.
+    codec: Codec,
+}
+
+impl DiffData {
+    pub(crate) fn new(
+        path: String,
+        value: &dyn Reflect,
+        registry: &TypeRegistry,
+        codec: Codec,
+    ) -> Result<Self, Error> {
+        let data = codec.encode(&TypedReflectSerializer::new(value, registry))?;
+        Ok(Self { path, data, codec })
+    }
+
+    /// Deserialize the value, whose type is described by `registration`.
+    pub(crate) fn deserialize(
+        &self,
+        registration: &TypeRegistration,
+        registry: &TypeRegistry,
+    ) -> Result<Box<dyn Reflect>, Error> {
+        self.codec.decode_seed(
+            TypedReflectDeserializer::new(registration, registry),
+            &self.data,
+        )
+    }
+
+    /// Deserialize the value and write it into the field of `target` designated
+    /// by the path of this data.
+    fn apply(&self, target: &mut dyn Reflect, registry: &TypeRegistry) -> Result<(), Error> {
+        let field = target
+            .reflect_path_mut(self.path.as_str())
+            .map_err(|err| self.invalid_path(err))?;
+        let type_info = field
+            .get_represented_type_info()
+            .ok_or_else(|| Error::UnregisteredType(field.reflect_type_path().to_string()))?;
+        let registration = registry
+            .get(type_info.type_id())
+            .ok_or_else(|| Error::UnregisteredType(type_info.type_path().to_string()))?;
+        let value = self.deserialize(registration, registry)?;
+        assign(field, value.as_ref());
+        Ok(())
+    }
+
+    /// Write the value into the field of `target` designated by the path of
+    /// this data, like [`apply()`], and return the previous value of that
+    /// field.
+    ///
+    /// [`apply()`]: DiffData::apply
+    pub(crate) fn replace(
+        &self,
+        target: &mut dyn Reflect,
+        registry: &TypeRegistry,
+    ) -> Result<DiffData, Error> {
+        let field = target
+            .reflect_path(self.path.as_str())
+            .map_err(|err| self.invalid_path(err))?;
+        let old = DiffData::new(self.path.clone(), field, registry, self.codec)?;
+        self.apply(target, registry)?;
+        Ok(old)
+    }
+
+    fn invalid_path(&self, err: ReflectPathError) -> Error {
+        Error::InvalidPath {
+            path: self.path.clone(),
+            reason: err.to_string(),
+        }
+    }
+
+    /// Reflect path of the value, relative to the root object.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Serialized value.
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Format of the serialized value.
+    pub fn codec(&self) -> Codec {
+        self.codec
+    }
 }
 
-#[derive(Serialize, Deserialize)]
-enum DiffContent {
+/// Content of a diff for a single change.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum DiffContent {
+    /// One-way change, recording only the new value.
     Single(DiffData),
+    /// Two-way change, recording the old and new values, in that order.
     Dual(DiffData, DiffData),
+    /// Component inserted on the target entity, recording the entire component
+    /// value.
+    Insert(DiffData),
+    /// Component removed from the target entity, optionally recording the
+    /// entire old component value for two-way diffs.
+    Remove(Option<DiffData>),
+    /// Target entity spawned.
+    Spawn,
+    /// Target entity despawned.
+    Despawn,
 }
 
-#[derive(Serialize, Deserialize)]
-struct Diff {
-    target: DiffTarget,
-    content: DiffContent,
+impl DiffContent {
+    /// The new value of the change, if any.
+    pub fn new_value(&self) -> Option<&DiffData> {
+        match self {
+            DiffContent::Single(data) | DiffContent::Dual(_, data) | DiffContent::Insert(data) => {
+                Some(data)
+            }
+            _ => None,
+        }
+    }
+
+    /// The reflect path of the changed value, relative to the target component.
+    ///
+    /// The path is empty for a component inserted or removed, and is `None`
+    /// for an entity spawned or despawned.
+    pub fn path(&self) -> Option<&str> {
+        match self {
+            DiffContent::Single(data) | DiffContent::Dual(_, data) => Some(data.path()),
+            DiffContent::Insert(_) | DiffContent::Remove(_) => Some(""),
+            DiffContent::Spawn | DiffContent::Despawn => None,
+        }
+    }
+
+    /// The old value of the change, if recorded.
+    pub fn old_value(&self) -> Option<&DiffData> {
+        match self {
+            DiffContent::Dual(data, _) => Some(data),
+            DiffContent::Remove(data) => data.as_ref(),
+            _ => None,
+        }
+    }
+
+    /// Invert the change, if it's two-way.
+    fn invert(&self) -> Option<DiffContent> {
+        match self {
+            DiffContent::Single(_) | DiffContent::Remove(None) => None,
+            DiffContent::Dual(old, new) => Some(DiffContent::Dual(new.clone(), old.clone())),
+            DiffContent::Insert(data) => Some(DiffContent::Remove(Some(data.clone()))),
+            DiffContent::Remove(Some(data)) => Some(DiffContent::Insert(data.clone())),
+            DiffContent::Spawn => Some(DiffContent::Despawn),
+            DiffContent::Despawn => Some(DiffContent::Spawn),
+        }
+    }
+}
+
+/// Diff between two instances of a `Reflect` type.
+///
+/// A diff is made of a collection of changed values, each identified by its
+/// reflect path relative to the root object.
+///
+/// A diff is either _one-way_, also called a _patch_, recording only the new
+/// values, or _two-way_, recording both the old and new values. A two-way diff
+/// can be inverted to undo the change it represents.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Diff {
+    target: Option<DiffTarget>,
+    content: Vec<DiffContent>,
 }
 
 impl Diff {
-    pub fn make<T: Reflect>(base: &T, curr: &T) -> Diff {
-        match base.reflect_ref() {
-            ReflectRef::Struct(s) => {
-                println!(
-                    "struct {}",
-                    s.get_represented_type_info().unwrap().type_path()
-                );
-                for f in s.iter_fields() {
-                    println!("{:?}", f);
+    /// Create a new diff for a target from its raw content.
+    pub fn new(target: DiffTarget, content: Vec<DiffContent>) -> Self {
+        Self {
+            target: Some(target),
+            content,
+        }
+    }
+
+    /// Make a new diff between a base value and a current value.
+    ///
+    /// The diff contains one [`DiffData`] per changed leaf value, in the order
+    /// the leaves are visited. Leaves are either values without any inner
+    /// structure (`ReflectRef::Value`), or values whose structure changed
+    /// between `base` and `curr` (list length changed, different enum variant,
+    /// any change to a map), in which case the entire value is recorded.
+    ///
+    /// All leaf types must be registered in the `registry` with their
+    /// serialization type data.
+    pub fn make(
+        base: &dyn Reflect,
+        curr: &dyn Reflect,
+        registry: &TypeRegistry,
+    ) -> Result<Diff, Error> {
+        Self::make_impl(base, curr, registry, false, Codec::Ron)
+    }
+
+    /// Make a new two-way diff between a base value and a current value.
+    ///
+    /// This is similar to [`make()`], but records both the old and new values
+    /// of each changed leaf, producing [`DiffContent::Dual`] content. The
+    /// resulting diff can be [`invert()`]ed.
+    ///
+    /// [`make()`]: Diff::make
+    /// [`invert()`]: Diff::invert
+    pub fn make_two_way(
+        base: &dyn Reflect,
+        curr: &dyn Reflect,
+        registry: &TypeRegistry,
+    ) -> Result<Diff, Error> {
+        Self::make_impl(base, curr, registry, true, Codec::Ron)
+    }
+
+    pub(crate) fn make_impl(
+        base: &dyn Reflect,
+        curr: &dyn Reflect,
+        registry: &TypeRegistry,
+        two_way: bool,
+        codec: Codec,
+    ) -> Result<Diff, Error> {
+        let mut content = vec![];
+        diff_values(
+            base,
+            curr,
+            &mut String::new(),
+            &mut |path: &str, base: &dyn Reflect, curr: &dyn Reflect| {
+                let new = DiffData::new(path.to_string(), curr, registry, codec)?;
+                if two_way {
+                    let old = DiffData::new(path.to_string(), base, registry, codec)?;
+                    content.push(DiffContent::Dual(old, new));
+                } else {
+                    content.push(DiffContent::Single(new));
+                }
+                Ok(())
+            },
+        )?;
+        Ok(Diff {
+            target: None,
+            content,
+        })
+    }
+
+    /// Set the target of the diff.
+    pub fn with_target(mut self, target: DiffTarget) -> Self {
+        self.target = Some(target);
+        self
+    }
+
+    /// The target of the diff, if any.
+    pub fn target(&self) -> Option<&DiffTarget> {
+        self.target.as_ref()
+    }
+
+    /// Check if the diff is two-way, that is it records old values and can be
+    /// inverted.
+    ///
+    /// An empty diff is trivially two-way.
+    pub fn is_two_way(&self) -> bool {
+        self.content
+            .iter()
+            .all(|content| !matches!(content, DiffContent::Single(_) | DiffContent::Remove(None)))
+    }
+
+    /// Invert a two-way diff.
+    ///
+    /// The inverted diff swaps the old and new values of all changes, such that
+    /// applying the inverted diff reverts the changes of the original diff.
+    /// Returns `None` if the diff is not two-way.
+    pub fn invert(&self) -> Option<Diff> {
+        let content = self
+            .content
+            .iter()
+            .rev()
+            .map(DiffContent::invert)
+            .collect::<Option<Vec<_>>>()?;
+        Some(Diff {
+            target: self.target.clone(),
+            content,
+        })
+    }
+
+    /// Apply the diff to a target value.
+    ///
+    /// For each changed value recorded in the diff, resolve its path on the
+    /// `target` and overwrite the value found there with the recorded one. When
+    /// `target` is equal to the `base` value the diff was made from, then after
+    /// this call `target` is equal to the `curr` value the diff was made from.
+    ///
+    /// Values are applied in order, and the first error aborts, leaving the
+    /// `target` partially modified.
+    ///
+    /// Changes to entities ([`DiffContent::Spawn`], [`DiffContent::Despawn`])
+    /// and component removals ([`DiffContent::Remove`]) can only be applied to
+    /// a world; see [`apply_world()`].
+    ///
+    /// [`apply_world()`]: Diff::apply_world
+    pub fn apply(&self, target: &mut dyn Reflect, registry: &TypeRegistry) -> Result<(), Error> {
+        for content in &self.content {
+            content
+                .new_value()
+                .ok_or(Error::InvalidDiff)?
+                .apply(target, registry)?;
+        }
+        Ok(())
+    }
+
+    /// Apply the diff to its target in the given world.
+    ///
+    /// The diff must have a target, whose component type is registered in the
+    /// `registry` with its [`ReflectComponent`] type data. See [`apply()`] for
+    /// details.
+    ///
+    /// [`apply()`]: Diff::apply
+    pub fn apply_world(&self, world: &mut World, registry: &TypeRegistry) -> Result<(), Error> {
+        self.apply_world_mapped(world, registry, &mut EntityHashMap::default())
+    }
+
+    /// Apply the diff to its target in the given world, mapping entities.
+    ///
+    /// This is similar to [`apply_world()`], but the target entity is first
+    /// looked up in `entity_map`, and the mapped entity is used instead if
+    /// found. Entities spawned by the diff are added to the map, and despawned
+    /// ones removed from it. This allows applying a sequence of diffs made
+    /// between two [`Snapshot`]s to another world where entities have
+    /// different identifiers.
+    ///
+    /// [`apply_world()`]: Diff::apply_world
+    /// [`Snapshot`]: crate::snapshot::Snapshot
+    pub fn apply_world_mapped(
+        &self,
+        world: &mut World,
+        registry: &TypeRegistry,
+        entity_map: &mut EntityHashMap<Entity>,
+    ) -> Result<(), Error> {
+        let target = self.target.as_ref().ok_or(Error::NoTarget)?;
+        for content in &self.content {
+            let mapped = target.mapped(entity_map);
+            let entity = mapped.entity;
+            match content {
+                DiffContent::Single(data) | DiffContent::Dual(_, data) => {
+                    let mut component = mapped.resolve_reflect_mut(world, registry)?;
+                    data.apply(component.as_reflect_mut(), registry)?;
+                }
+                DiffContent::Insert(data) => {
+                    let registration = mapped.registration(registry)?;
+                    let reflect_component = mapped.reflect_component(registry)?;
+                    let value = data.deserialize(registration, registry)?;
+                    let mut entity_mut = world
+                        .get_entity_mut(entity)
+                        .ok_or(Error::EntityNotFound(entity))?;
+                    reflect_component.insert(&mut entity_mut, value.as_ref(), registry);
+                }
+                DiffContent::Remove(_) => {
+                    let reflect_component = mapped.reflect_component(registry)?;
+                    let entity_ref = world
+                        .get_entity(entity)
+                        .ok_or(Error::EntityNotFound(entity))?;
+                    if !reflect_component.contains(entity_ref) {
+                        return Err(mapped.component_not_found());
+                    }
+                    reflect_component.remove(&mut world.entity_mut(entity));
+                }
+                DiffContent::Spawn => {
+                    let spawned = world.spawn_empty().id();
+                    entity_map.insert(target.entity, spawned);
+                }
+                DiffContent::Despawn => {
+                    if !world.despawn(entity) {
+                        return Err(Error::EntityNotFound(entity));
+                    }
+                    entity_map.remove(&target.entity);
                 }
             }
-            _ => (),
         }
-        Diff {
-            target: DiffTarget {
-                entity: Entity::from_raw(0),
-                component: 0,
-            },
-            content: DiffContent::Single(DiffData {
-                path: "".to_string(),
-                data: vec![],
-            }),
+        Ok(())
+    }
+
+    /// Check if the diff is empty, that is there's no change recorded.
+    pub fn is_empty(&self) -> bool {
+        self.content.is_empty()
+    }
+
+    /// Content of the diff, one entry per change.
+    pub fn content(&self) -> &[DiffContent] {
+        &self.content
+    }
+}
+
+/// Conflict between changes of two diff sets merged with [`merge()`].
+///
+/// Each conflicting change is returned as a diff with a single content entry.
+/// Applying either the `ours` or the `theirs` diffs to the result of the merge
+/// resolves the conflict in favor of that side.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MergeConflict {
+    /// Entity targetted by the conflicting changes.
+    pub entity: Entity,
+    /// Conflicting changes of the first diff set.
+    pub ours: Vec<Diff>,
+    /// Conflicting changes of the second diff set.
+    pub theirs: Vec<Diff>,
+}
+
+/// Result of a three-way [`merge()`].
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Merge {
+    /// Merged changes, to apply in order to the common base.
+    pub diffs: Vec<Diff>,
+    /// Conflicting changes, not included in `diffs`.
+    pub conflicts: Vec<MergeConflict>,
+}
+
+impl Merge {
+    /// Check if the merge has no conflict.
+    pub fn is_clean(&self) -> bool {
+        self.conflicts.is_empty()
+    }
+}
+
+/// Single change of a diff set.
+struct Change<'a> {
+    target: &'a DiffTarget,
+    content: &'a DiffContent,
+}
+
+impl Change<'_> {
+    /// Field modified by the change, or `None` if it modifies the entity
+    /// itself.
+    fn field(&self) -> Option<FieldPath> {
+        let component = self.target.component()?;
+        Some(FieldPath::new(component, self.content.path()?))
+    }
+
+    fn overlaps(&self, other: &Change) -> bool {
+        self.target.entity == other.target.entity
+            && match (self.field(), other.field()) {
+                (Some(field), Some(other)) => field.overlaps(&other),
+                _ => true,
+            }
+    }
+
+    /// Check if both changes produce the same result.
+    ///
+    /// Two spawns are never the same: entities spawned independently by each
+    /// side are unrelated, even if they got the same identifier.
+    fn same_as(&self, other: &Change) -> bool {
+        use DiffContent::*;
+        match (self.content, other.content) {
+            (Single(_) | Dual(..), Single(_) | Dual(..)) | (Insert(_), Insert(_)) => {
+                self.content.new_value() == other.content.new_value()
+            }
+            (Remove(_), Remove(_)) | (Despawn, Despawn) => true,
+            _ => false,
+        }
+    }
+
+    fn to_diff(&self) -> Diff {
+        Diff::new(self.target.clone(), vec![self.content.clone()])
+    }
+}
+
+/// Flatten a diff set into its individual changes, in order.
+fn changes(diffs: &[Diff]) -> Result<Vec<Change<'_>>, Error> {
+    let mut changes = vec![];
+    for diff in diffs {
+        let target = diff.target().ok_or(Error::NoTarget)?;
+        changes.extend(
+            diff.content()
+                .iter()
+                .map(|content| Change { target, content }),
+        );
+    }
+    Ok(changes)
+}
+
+/// Merge two diff sets made independently from a common base.
+///
+/// This is a three-way merge: `ours` and `theirs` are each the diffs from the
+/// common base to one modified version, as produced by [`Snapshot::diff()`].
+/// Changes of one side not overlapping any change of the other side are merged
+/// automatically. Two changes overlap when they target the same entity, and
+/// either modify the same field or a field containing the other, or one of
+/// them inserts, removes, spawns, or despawns what the other modifies.
+/// Overlapping changes producing the same result, like setting a field to the
+/// same value, are merged once. Other overlapping changes are conflicts,
+/// returned as [`MergeConflict`]s grouping all the changes overlapping each
+/// other. In particular, both sides spawning an entity with the same identifier
+/// is a conflict, since the two entities are unrelated.
+///
+/// The merged diffs contain the changes of `ours` then the ones of `theirs`, in
+/// their original order, such that applying them to the common base produces
+/// the merged version. All diffs must have a target, otherwise this returns an
+/// [`Error::NoTarget`].
+///
+/// [`Snapshot::diff()`]: crate::snapshot::Snapshot::diff
+pub fn merge(ours: &[Diff], theirs: &[Diff]) -> Result<Merge, Error> {
+    let ours_changes = changes(ours)?;
+    let theirs_changes = changes(theirs)?;
+    let count = ours_changes.len();
+
+    // Union-find of the conflicting changes, indexing the changes of `theirs`
+    // after the ones of `ours`.
+    let mut groups: Vec<usize> = (0..count + theirs_changes.len()).collect();
+    fn root(groups: &mut [usize], mut index: usize) -> usize {
+        while groups[index] != index {
+            groups[index] = groups[groups[index]];
+            index = groups[index];
+        }
+        index
+    }
+    let mut conflicting = vec![false; groups.len()];
+    let mut duplicate = vec![false; theirs_changes.len()];
+    for (i, our) in ours_changes.iter().enumerate() {
+        for (j, their) in theirs_changes.iter().enumerate() {
+            if !our.overlaps(their) {
+                continue;
+            }
+            if our.same_as(their) {
+                duplicate[j] = true;
+                continue;
+            }
+            conflicting[i] = true;
+            conflicting[count + j] = true;
+            let (a, b) = (root(&mut groups, i), root(&mut groups, count + j));
+            groups[b] = a;
+        }
+    }
+
+    let mut merge = Merge::default();
+    let mut roots: Vec<usize> = vec![];
+    let all_changes = ours_changes.iter().chain(theirs_changes.iter());
+    for (index, change) in all_changes.enumerate() {
+        if !conflicting[index] {
+            continue;
+        }
+        let group = root(&mut groups, index);
+        let conflict = match roots.iter().position(|root| *root == group) {
+            Some(position) => &mut merge.conflicts[position],
+            None => {
+                roots.push(group);
+                merge.conflicts.push(MergeConflict {
+                    entity: change.target.entity,
+                    ours: vec![],
+                    theirs: vec![],
+                });
+                merge.conflicts.last_mut().unwrap()
+            }
+        };
+        if index < count {
+            conflict.ours.push(change.to_diff());
+        } else {
+            conflict.theirs.push(change.to_diff());
+        }
+    }
+
+    let mut index = 0;
+    for diff in ours.iter().chain(theirs.iter()) {
+        let mut content = vec![];
+        for change in &diff.content {
+            let skip = conflicting[index] || (index >= count && duplicate[index - count]);
+            if !skip {
+                content.push(change.clone());
+            }
+            index += 1;
+        }
+        if !content.is_empty() {
+            merge.diffs.push(Diff {
+                target: diff.target.clone(),
+                content,
+            });
+        }
+    }
+    Ok(merge)
+}
+
+/// Callback invoked with the path, base value, and current value of a changed
+/// leaf.
+type EmitFn<'a> = dyn FnMut(&str, &dyn Reflect, &dyn Reflect) -> Result<(), Error> + 'a;
+
+/// Recursively compare two values and invoke `emit` with the path of each
+/// changed leaf and its base and current values.
+///
+/// The `path` is used as a scratch buffer to build the reflect path of nested
+/// values; it's restored to its original content on return.
+fn diff_values(
+    base: &dyn Reflect,
+    curr: &dyn Reflect,
+    path: &mut String,
+    emit: &mut EmitFn,
+) -> Result<(), Error> {
+    match (base.reflect_ref(), curr.reflect_ref()) {
+        (ReflectRef::Struct(b), ReflectRef::Struct(c)) if same_struct_fields(b, c) => {
+            for (index, value) in c.iter_fields().enumerate() {
+                let name = c.name_at(index).unwrap();
+                diff_child(b.field(name).unwrap(), value, path, '.', name, emit)?;
+            }
+            Ok(())
+        }
+        (ReflectRef::TupleStruct(b), ReflectRef::TupleStruct(c))
+            if b.field_len() == c.field_len() =>
+        {
+            for (index, (b, c)) in b.iter_fields().zip(c.iter_fields()).enumerate() {
+                diff_child(b, c, path, '.', index, emit)?;
+            }
+            Ok(())
+        }
+        (ReflectRef::Tuple(b), ReflectRef::Tuple(c)) if b.field_len() == c.field_len() => {
+            for (index, (b, c)) in b.iter_fields().zip(c.iter_fields()).enumerate() {
+                diff_child(b, c, path, '.', index, emit)?;
+            }
+            Ok(())
+        }
+        (ReflectRef::List(b), ReflectRef::List(c)) if b.len() == c.len() => {
+            for (index, (b, c)) in b.iter().zip(c.iter()).enumerate() {
+                diff_child(b, c, path, '[', index, emit)?;
+            }
+            Ok(())
+        }
+        (ReflectRef::Array(b), ReflectRef::Array(c)) if b.len() == c.len() => {
+            for (index, (b, c)) in b.iter().zip(c.iter()).enumerate() {
+                diff_child(b, c, path, '[', index, emit)?;
+            }
+            Ok(())
+        }
+        (ReflectRef::Enum(b), ReflectRef::Enum(c)) if same_enum_variant(b, c) => {
+            // Unit variants have no field, so are equal if the variant is the same.
+            for (index, field) in c.iter_fields().enumerate() {
+                if let Some(name) = field.name() {
+                    let base = b.field(name).unwrap();
+                    diff_child(base, field.value(), path, '.', name, emit)?;
+                } else {
+                    let base = b.field_at(index).unwrap();
+                    diff_child(base, field.value(), path, '.', index, emit)?;
+                }
+            }
+            Ok(())
+        }
+        // Map entries cannot be addressed by a reflect path, so any change to a map
+        // is recorded as a change of the entire map. Values which cannot be
+        // compared are conservatively assumed to be different.
+        _ => {
+            if base.reflect_partial_eq(curr) != Some(true) {
+                emit(path, base, curr)
+            } else {
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Append the access to a child value to `path`, and diff that child.
+fn diff_child(
+    base: &dyn Reflect,
+    curr: &dyn Reflect,
+    path: &mut String,
+    prefix: char,
+    access: impl std::fmt::Display,
+    emit: &mut EmitFn,
+) -> Result<(), Error> {
+    use std::fmt::Write;
+    let len = path.len();
+    if prefix == '[' {
+        write!(path, "[{}]", access).unwrap();
+    } else {
+        write!(path, "{}{}", prefix, access).unwrap();
+    }
+    let res = diff_values(base, curr, path, emit);
+    path.truncate(len);
+    res
+}
+
+/// Overwrite `target` with `value`.
+///
+/// This is similar to [`Reflect::apply()`], except that extra list elements
+/// and map entries not present in `value` are removed from `target`, so that
+/// both compare equal after the call.
+fn assign(target: &mut dyn Reflect, value: &dyn Reflect) {
+    match (target.reflect_mut(), value.reflect_ref()) {
+        (ReflectMut::Struct(t), ReflectRef::Struct(v)) => {
+            for (index, value) in v.iter_fields().enumerate() {
+                if let Some(field) = t.field_mut(v.name_at(index).unwrap()) {
+                    assign(field, value);
+                }
+            }
+            return;
         }
+        (ReflectMut::TupleStruct(t), ReflectRef::TupleStruct(v)) => {
+            for (index, value) in v.iter_fields().enumerate() {
+                if let Some(field) = t.field_mut(index) {
+                    assign(field, value);
+                }
+            }
+            return;
+        }
+        (ReflectMut::Tuple(t), ReflectRef::Tuple(v)) => {
+            for (index, value) in v.iter_fields().enumerate() {
+                if let Some(field) = t.field_mut(index) {
+                    assign(field, value);
+                }
+            }
+            return;
+        }
+        (ReflectMut::Array(t), ReflectRef::Array(v)) => {
+            for (index, value) in v.iter().enumerate() {
+                if let Some(item) = t.get_mut(index) {
+                    assign(item, value);
+                }
+            }
+            return;
+        }
+        (ReflectMut::List(t), ReflectRef::List(v)) => {
+            while t.len() > v.len() {
+                t.pop();
+            }
+            for (index, value) in v.iter().enumerate() {
+                if let Some(item) = t.get_mut(index) {
+                    assign(item, value);
+                } else {
+                    t.push(value.clone_value());
+                }
+            }
+            return;
+        }
+        (ReflectMut::Map(t), ReflectRef::Map(v)) => {
+            let removed: Vec<Box<dyn Reflect>> = t
+                .iter()
+                .filter(|(key, _)| v.get(*key).is_none())
+                .map(|(key, _)| key.clone_value())
+                .collect();
+            for key in removed {
+                t.remove(key.as_ref());
+            }
+            for (key, value) in v.iter() {
+                if let Some(entry) = t.get_mut(key) {
+                    assign(entry, value);
+                } else {
+                    t.insert_boxed(key.clone_value(), value.clone_value());
+                }
+            }
+            return;
+        }
+        (ReflectMut::Enum(t), ReflectRef::Enum(v)) if same_enum_variant(t, v) => {
+            for (index, value) in v.iter_fields().enumerate() {
+                let field = match value.name() {
+                    Some(name) => t.field_mut(name),
+                    None => t.field_at_mut(index),
+                };
+                if let Some(field) = field {
+                    assign(field, value.value());
+                }
+            }
+            return;
+        }
+        _ => {}
     }
+    // Values, enums changing variant, and mismatching kinds
+    target.apply(value);
+}
+
+fn same_struct_fields(base: &dyn Struct, curr: &dyn Struct) -> bool {
+    base.field_len() == curr.field_len()
+        && (0..curr.field_len()).all(|index| base.field(curr.name_at(index).unwrap()).is_some())
+}
+
+fn same_enum_variant(base: &dyn Enum, curr: &dyn Enum) -> bool {
+    base.variant_type() == curr.variant_type()
+        && base.variant_name() == curr.variant_name()
+        && base.field_len() == curr.field_len()
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use bevy::{ecs::reflect::ReflectComponent, utils::HashMap};
 
-    #[derive(Reflect)]
+    #[derive(Debug, Clone, PartialEq, Reflect)]
     struct S {
         f: f32,
         i: i32,
     }
 
+    #[derive(Debug, Clone, PartialEq, Reflect)]
+    enum E {
+        A,
+        B(u32, f32),
+        C { s: S },
+    }
+
+    #[derive(Debug, Clone, PartialEq, Reflect)]
+    struct T(u8, String);
+
+    #[derive(Debug, Clone, PartialEq, Reflect)]
+    struct Nested {
+        s: S,
+        t: T,
+        tuple: (bool, u16),
+        list: Vec<i32>,
+        array: [f32; 3],
+        map: HashMap<u32, String>,
+        e: E,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Component, Reflect)]
+    #[reflect(Component)]
+    struct C {
+        s: S,
+    }
+
+    #[derive(Component)]
+    struct D;
+
+    fn registry() -> TypeRegistry {
+        let mut registry = TypeRegistry::default();
+        registry.register::<S>();
+        registry.register::<E>();
+        registry.register::<T>();
+        registry.register::<(bool, u16)>();
+        registry.register::<Vec<i32>>();
+        registry.register::<[f32; 3]>();
+        registry.register::<HashMap<u32, String>>();
+        registry.register::<Nested>();
+        registry.register::<C>();
+        registry
+    }
+
+    fn nested() -> Nested {
+        Nested {
+            s: S { f: 3., i: -42 },
+            t: T(5, "abc".to_string()),
+            tuple: (true, 8),
+            list: vec![1, 2, 3],
+            array: [1., 2., 3.],
+            map: HashMap::from([(1, "one".to_string())]),
+            e: E::B(4, 5.),
+        }
+    }
+
+    fn roundtrip<T: Reflect + Clone + PartialEq + std::fmt::Debug>(
+        base: &T,
+        curr: &T,
+        registry: &TypeRegistry,
+    ) {
+        let diff = Diff::make(base, curr, registry).unwrap();
+        let mut target = base.clone();
+        diff.apply(&mut target, registry).unwrap();
+        assert_eq!(&target, curr);
+    }
+
+    fn paths(diff: &Diff) -> Vec<&str> {
+        diff.content()
+            .iter()
+            .map(|content| content.new_value().unwrap().path())
+            .collect()
+    }
+
     #[test]
     fn diff_make() {
+        let registry = registry();
         let base = S { f: 3., i: -42 };
         let curr = S { f: 5., i: -420 };
-        let diff = Diff::make(&base, &curr);
+        let diff = Diff::make(&base, &curr, &registry).unwrap();
+        assert_eq!(paths(&diff), vec![".f", ".i"]);
+        let DiffContent::Single(data) = &diff.content()[0] else {
+            panic!("Unexpected dual diff content.");
+        };
+        assert_eq!(data.data(), b"5.0");
+    }
+
+    #[test]
+    fn diff_make_same() {
+        let registry = registry();
+        let diff = Diff::make(&nested(), &nested(), &registry).unwrap();
+        assert!(diff.is_empty());
+    }
+
+    #[test]
+    fn diff_make_value() {
+        let registry = registry();
+        let diff = Diff::make(&3_u32, &4_u32, &registry).unwrap();
+        assert_eq!(paths(&diff), vec![""]);
+    }
+
+    #[test]
+    fn diff_make_nested() {
+        let registry = registry();
+        let base = nested();
+        let mut curr = nested();
+        curr.s.i = 0;
+        curr.t.1 = "def".to_string();
+        curr.tuple.0 = false;
+        curr.list[1] = 5;
+        curr.array[2] = 0.;
+        curr.map.insert(2, "two".to_string());
+        curr.e = E::B(4, 6.);
+        let diff = Diff::make(&base, &curr, &registry).unwrap();
+        assert_eq!(
+            paths(&diff),
+            vec![
+                ".s.i",
+                ".t.1",
+                ".tuple.0",
+                ".list[1]",
+                ".array[2]",
+                ".map",
+                ".e.1"
+            ]
+        );
+    }
+
+    #[test]
+    fn diff_make_structural() {
+        let registry = registry();
+        let base = nested();
+        let mut curr = nested();
+        curr.list.push(4);
+        curr.e = E::C {
+            s: S { f: 0., i: 0 },
+        };
+        let diff = Diff::make(&base, &curr, &registry).unwrap();
+        assert_eq!(paths(&diff), vec![".list", ".e"]);
+
+        let base = E::C {
+            s: S { f: 0., i: 0 },
+        };
+        let curr = E::C {
+            s: S { f: 0., i: 1 },
+        };
+        let diff = Diff::make(&base, &curr, &registry).unwrap();
+        assert_eq!(paths(&diff), vec![".s.i"]);
+
+        let diff = Diff::make(&E::A, &E::A, &registry).unwrap();
+        assert!(diff.is_empty());
+    }
+
+    #[test]
+    fn diff_apply() {
+        let registry = registry();
+        roundtrip(&3_u32, &4_u32, &registry);
+        roundtrip(&S { f: 3., i: -42 }, &S { f: 5., i: -420 }, &registry);
+        roundtrip(&T(0, "a".to_string()), &T(1, "b".to_string()), &registry);
+        roundtrip(&(true, 8_u16), &(false, 9_u16), &registry);
+        roundtrip(&vec![1, 2, 3], &vec![1, 5, 3], &registry);
+        roundtrip(&vec![1, 2, 3], &vec![1, 2], &registry);
+        roundtrip(&vec![1, 2, 3], &vec![4, 5, 6, 7], &registry);
+        roundtrip(&[1_f32, 2., 3.], &[1., 5., 3.], &registry);
+        let map = HashMap::from([(1_u32, "one".to_string()), (2, "two".to_string())]);
+        roundtrip(&map, &HashMap::from([(1, "un".to_string())]), &registry);
+        roundtrip(&map, &HashMap::new(), &registry);
+        roundtrip(&E::A, &E::B(3, 4.), &registry);
+        roundtrip(&E::B(3, 4.), &E::B(3, 5.), &registry);
+        roundtrip(
+            &E::B(3, 4.),
+            &E::C {
+                s: S { f: 0., i: 1 },
+            },
+            &registry,
+        );
+        roundtrip(
+            &E::C {
+                s: S { f: 0., i: 1 },
+            },
+            &E::C {
+                s: S { f: 2., i: 1 },
+            },
+            &registry,
+        );
+        roundtrip(&E::B(3, 4.), &E::A, &registry);
+    }
+
+    #[test]
+    fn diff_apply_nested() {
+        let registry = registry();
+        let base = nested();
+        let mut curr = nested();
+        curr.s.i = 0;
+        curr.t.1 = "def".to_string();
+        curr.tuple.0 = false;
+        curr.list = vec![5];
+        curr.array[2] = 0.;
+        curr.map.remove(&1);
+        curr.map.insert(2, "two".to_string());
+        curr.e = E::C {
+            s: S { f: 0., i: 0 },
+        };
+        roundtrip(&base, &curr, &registry);
+        roundtrip(&curr, &base, &registry);
+    }
+
+    #[test]
+    fn diff_apply_error() {
+        let registry = registry();
+        let diff = Diff::make(&S { f: 3., i: 0 }, &S { f: 4., i: 0 }, &registry).unwrap();
+
+        // Path doesn't exist on target
+        let mut target = T(0, "a".to_string());
+        assert!(matches!(
+            diff.apply(&mut target, &registry),
+            Err(Error::InvalidPath { path, .. }) if path == ".f"
+        ));
+
+        // Type of field not registered
+        let mut target = S { f: 3., i: 0 };
+        assert!(matches!(
+            diff.apply(&mut target, &TypeRegistry::empty()),
+            Err(Error::UnregisteredType(type_path)) if type_path == "f32"
+        ));
+    }
+
+    #[test]
+    fn diff_two_way() {
+        let registry = registry();
+        let base = nested();
+        let mut curr = nested();
+        curr.s.f = 8.;
+        curr.list = vec![];
+        curr.e = E::A;
+
+        let diff = Diff::make(&base, &curr, &registry).unwrap();
+        assert!(!diff.is_two_way());
+        assert!(diff.invert().is_none());
+
+        let diff = Diff::make_two_way(&base, &curr, &registry).unwrap();
+        assert!(diff.is_two_way());
+        assert_eq!(paths(&diff), vec![".s.f", ".list", ".e"]);
+        let DiffContent::Dual(old, new) = &diff.content()[0] else {
+            panic!("Expected dual diff content.");
+        };
+        assert_eq!(old.data(), b"3.0");
+        assert_eq!(new.data(), b"8.0");
+
+        let mut target = base.clone();
+        diff.apply(&mut target, &registry).unwrap();
+        assert_eq!(target, curr);
+
+        let inv = diff.invert().unwrap();
+        assert!(inv.is_two_way());
+        assert_eq!(paths(&inv), vec![".e", ".list", ".s.f"]);
+        inv.apply(&mut target, &registry).unwrap();
+        assert_eq!(target, base);
+        assert_eq!(inv.invert().unwrap(), diff);
+    }
+
+    #[test]
+    fn target_resolve() {
+        let registry = registry();
+        let mut world = World::new();
+        let c = C {
+            s: S { f: 3., i: 0 },
+        };
+        let entity = world.spawn(c.clone()).id();
+        let target = DiffTarget::of::<C>(entity);
+        assert_eq!(target.entity(), entity);
+        assert_eq!(target.component(), Some(C::type_path()));
+
+        assert_eq!(target.resolve::<C>(&world).unwrap(), &c);
+        assert_eq!(target.resolve_mut::<C>(&mut world).unwrap().as_ref(), &c);
+        assert!(target
+            .resolve_reflect(&world, &registry)
+            .unwrap()
+            .reflect_partial_eq(&c)
+            .unwrap());
+        target
+            .resolve_reflect_mut(&mut world, &registry)
+            .unwrap()
+            .apply(&C {
+                s: S { f: 4., i: 0 },
+            });
+        assert_eq!(target.resolve::<C>(&world).unwrap().s.f, 4.);
+
+        // Wrong component type
+        assert!(matches!(
+            DiffTarget::new(entity, "x::Y").resolve::<C>(&world),
+            Err(Error::ComponentMismatch { expected, actual: Some(actual) })
+                if expected == C::type_path() && actual == "x::Y"
+        ));
+        assert!(matches!(
+            DiffTarget::new(entity, "x::Y").resolve_reflect(&world, &registry),
+            Err(Error::UnregisteredType(type_path)) if type_path == "x::Y"
+        ));
+
+        // Missing component
+        let other = world.spawn(D).id();
+        let target = DiffTarget::of::<C>(other);
+        assert!(matches!(
+            target.resolve::<C>(&world),
+            Err(Error::ComponentNotFound { entity, component })
+                if entity == other && component == C::type_path()
+        ));
+        assert!(matches!(
+            target.resolve_reflect_mut(&mut world, &registry),
+            Err(Error::ComponentNotFound { entity, .. }) if entity == other
+        ));
+
+        // Despawned entity
+        world.despawn(entity);
+        let target = DiffTarget::of::<C>(entity);
+        assert!(matches!(
+            target.resolve_mut::<C>(&mut world),
+            Err(Error::EntityNotFound(e)) if e == entity
+        ));
+        assert!(matches!(
+            target.resolve_reflect(&world, &registry),
+            Err(Error::EntityNotFound(e)) if e == entity
+        ));
+    }
+
+    #[test]
+    fn diff_content_invert() {
+        let registry = registry();
+        let data = DiffData::new(String::new(), &S { f: 0., i: 1 }, &registry, Codec::Ron).unwrap();
+        let mut world = World::new();
+        let entity = world.spawn_empty().id();
+        let target = DiffTarget::new(entity, C::type_path());
+        let diff = Diff::new(target.clone(), vec![DiffContent::Remove(None)]);
+        assert!(!diff.is_two_way());
+        assert!(diff.invert().is_none());
+        assert!(matches!(
+            diff.apply(&mut S { f: 0., i: 0 }, &registry),
+            Err(Error::InvalidDiff)
+        ));
+        assert!(matches!(
+            diff.apply_world(&mut world, &registry),
+            Err(Error::ComponentNotFound { entity: e, .. }) if e == entity
+        ));
+
+        let diff = Diff::new(target.clone(), vec![DiffContent::Insert(data.clone())]);
+        assert!(diff.is_two_way());
+        assert_eq!(
+            diff.invert().unwrap(),
+            Diff::new(target, vec![DiffContent::Remove(Some(data))])
+        );
+
+        let diff = Diff::new(DiffTarget::from_entity(entity), vec![DiffContent::Spawn]);
+        assert!(diff.is_two_way());
+        assert_eq!(diff.invert().unwrap().content(), &[DiffContent::Despawn]);
+    }
+
+    #[test]
+    fn diff_apply_world() {
+        let registry = registry();
+        let mut world = World::new();
+        let base = C {
+            s: S { f: 3., i: 0 },
+        };
+        let curr = C {
+            s: S { f: 3., i: 5 },
+        };
+        let entity = world.spawn(base.clone()).id();
+
+        let diff = Diff::make(&base, &curr, &registry).unwrap();
+        assert!(matches!(
+            diff.apply_world(&mut world, &registry),
+            Err(Error::NoTarget)
+        ));
+
+        let diff = diff.with_target(DiffTarget::of::<C>(entity));
+        diff.apply_world(&mut world, &registry).unwrap();
+        assert_eq!(world.get::<C>(entity), Some(&curr));
+    }
+
+    #[test]
+    fn diff_merge() {
+        let registry = registry();
+        let (e0, e1) = (Entity::from_raw(0), Entity::from_raw(1));
+        let base = C {
+            s: S { f: 3., i: 0 },
+        };
+        let make = |entity, f, i| {
+            let curr = C { s: S { f, i } };
+            Diff::make_two_way(&base, &curr, &registry)
+                .unwrap()
+                .with_target(DiffTarget::of::<C>(entity))
+        };
+
+        // Different fields, and same change to the same field
+        let ours = [make(e0, 5., 0), make(e1, 4., 0)];
+        let theirs = [make(e0, 3., 7), make(e1, 4., 0)];
+        let merge = merge(&ours, &theirs).unwrap();
+        assert!(merge.is_clean());
+        assert_eq!(merge.diffs.len(), 3);
+        let mut merged = base.clone();
+        for diff in merge
+            .diffs
+            .iter()
+            .filter(|diff| diff.target().unwrap().entity() == e0)
+        {
+            diff.apply(&mut merged, &registry).unwrap();
+        }
+        assert_eq!(merged.s, S { f: 5., i: 7 });
+
+        // Different changes to the same field, and despawn of a modified entity
+        let despawn = Diff::new(DiffTarget::from_entity(e1), vec![DiffContent::Despawn]);
+        let ours = [make(e0, 5., 1), make(e1, 4., 0)];
+        let theirs = [make(e0, 6., 0), despawn.clone()];
+        let merge = super::merge(&ours, &theirs).unwrap();
+        assert_eq!(merge.diffs.len(), 1);
+        assert_eq!(paths(&merge.diffs[0]), [".s.i"]);
+        assert_eq!(merge.conflicts.len(), 2);
+        let conflict = &merge.conflicts[0];
+        assert_eq!(conflict.entity, e0);
+        assert_eq!(paths(&conflict.ours[0]), [".s.f"]);
+        assert_eq!(paths(&conflict.theirs[0]), [".s.f"]);
+        assert_eq!(merge.conflicts[1].ours, [make(e1, 4., 0)]);
+        assert_eq!(merge.conflicts[1].theirs, [despawn]);
+
+        // Both sides spawn a different entity with the same identifier
+        let e2 = Entity::from_raw(2);
+        let spawn = |f, i| {
+            let data =
+                DiffData::new(String::new(), &C { s: S { f, i } }, &registry, Codec::Ron).unwrap();
+            [
+                Diff::new(DiffTarget::from_entity(e2), vec![DiffContent::Spawn]),
+                Diff::new(DiffTarget::of::<C>(e2), vec![DiffContent::Insert(data)]),
+            ]
+        };
+        let (ours, theirs) = (spawn(1., 1), spawn(2., 2));
+        let merge = super::merge(&ours, &theirs).unwrap();
+        assert!(merge.diffs.is_empty());
+        assert_eq!(merge.conflicts.len(), 1);
+        assert_eq!(merge.conflicts[0].entity, e2);
+        assert_eq!(merge.conflicts[0].ours, ours);
+        assert_eq!(merge.conflicts[0].theirs, theirs);
+
+        assert!(matches!(
+            super::merge(&[Diff::make(&base, &base, &registry).unwrap()], &[]),
+            Err(Error::NoTarget)
+        ));
     }
 }
@@ -0,0 +1,272 @@
+use bevy::app::{App, First, Plugin};
+use bevy::ecs::{
+    component::Component,
+    entity::{Entity, EntityHashMap},
+    query::Changed,
+    reflect::ReflectComponent,
+    removal_detection::RemovedComponents,
+    system::{Query, ResMut, Resource},
+    world::World,
+};
+use bevy::reflect::Reflect;
+use bevy::utils::{HashMap, Uuid};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+use super::error::Error;
+
+/// Stable identifier of an entity, shared across processes.
+///
+/// An [`Entity`] is only meaningful in the [`World`] which allocated it; the
+/// same entity has a different [`Entity`] value in the editor and in the game.
+/// Instead, entities exchanged between processes are identified by this
+/// component, which holds a random UUID assigned once and persisted with the
+/// entity. Each process maintains an [`EntityMap`] to find the local entity
+/// with a given identifier.
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    Hash,
+    PartialOrd,
+    Ord,
+    Component,
+    Reflect,
+    Serialize,
+    Deserialize,
+)]
+#[reflect(Component, Hash, PartialEq)]
+pub struct EntityId(Uuid);
+
+impl EntityId {
+    /// Create a new random identifier.
+    pub fn new() -> Self {
+        Self(Uuid::new_v4())
+    }
+
+    /// Create an identifier from an existing UUID.
+    pub fn from_uuid(uuid: Uuid) -> Self {
+        Self(uuid)
+    }
+
+    /// The UUID of the identifier.
+    pub fn uuid(&self) -> Uuid {
+        self.0
+    }
+}
+
+impl Default for EntityId {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Display for EntityId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// Bidirectional mapping between [`EntityId`]s and local entities.
+///
+/// The map is kept in sync with the [`EntityId`] components of the world by
+/// the [`EntityMapPlugin`], once per update in the [`First`] schedule. Entities
+/// can also be mapped manually, for example to map an entity spawned this
+/// update before the map is synchronized.
+#[derive(Debug, Default, Clone, Resource)]
+pub struct EntityMap {
+    entities: HashMap<EntityId, Entity>,
+    ids: EntityHashMap<EntityId>,
+}
+
+impl EntityMap {
+    /// Create a new empty map.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a map of all the entities of a world with an [`EntityId`].
+    pub fn from_world(world: &mut World) -> Self {
+        let mut map = Self::new();
+        for (entity, id) in world.query::<(Entity, &EntityId)>().iter(world) {
+            map.insert(*id, entity);
+        }
+        map
+    }
+
+    /// Map an identifier to a local entity.
+    ///
+    /// Any previous mapping of either the identifier or the entity is removed.
+    pub fn insert(&mut self, id: EntityId, entity: Entity) {
+        self.remove_id(id);
+        self.remove_entity(entity);
+        self.entities.insert(id, entity);
+        self.ids.insert(entity, id);
+    }
+
+    /// Remove the mapping of an identifier, and return the entity it was
+    /// mapped to, if any.
+    pub fn remove_id(&mut self, id: EntityId) -> Option<Entity> {
+        let entity = self.entities.remove(&id)?;
+        self.ids.remove(&entity);
+        Some(entity)
+    }
+
+    /// Remove the mapping of a local entity, and return the identifier it was
+    /// mapped to, if any.
+    pub fn remove_entity(&mut self, entity: Entity) -> Option<EntityId> {
+        let id = self.ids.remove(&entity)?;
+        self.entities.remove(&id);
+        Some(id)
+    }
+
+    /// Get the local entity with the given identifier, if any.
+    pub fn entity(&self, id: EntityId) -> Option<Entity> {
+        self.entities.get(&id).copied()
+    }
+
+    /// Get the identifier of a local entity, if any.
+    pub fn id(&self, entity: Entity) -> Option<EntityId> {
+        self.ids.get(&entity).copied()
+    }
+
+    /// Get the local entity with the given identifier, or an
+    /// [`Error::UnmappedEntity`] if none.
+    pub fn resolve(&self, id: EntityId) -> Result<Entity, Error> {
+        self.entity(id).ok_or(Error::UnmappedEntity(id))
+    }
+
+    /// Number of mapped entities.
+    pub fn len(&self) -> usize {
+        self.entities.len()
+    }
+
+    /// Check if the map is empty.
+    pub fn is_empty(&self) -> bool {
+        self.entities.is_empty()
+    }
+
+    /// Remove all mappings.
+    pub fn clear(&mut self) {
+        self.entities.clear();
+        self.ids.clear();
+    }
+
+    /// Iterate over all the mapped identifiers and their local entity.
+    pub fn iter(&self) -> impl Iterator<Item = (EntityId, Entity)> + '_ {
+        self.entities.iter().map(|(id, entity)| (*id, *entity))
+    }
+
+    /// Map the entities of this map to the entities of `other` with the same
+    /// identifier.
+    ///
+    /// This allows applying a [`Diff`] targetting an entity of one world to
+    /// another world with [`Diff::apply_world_mapped()`], when both worlds
+    /// identify their entities with an [`EntityId`].
+    ///
+    /// [`Diff`]: crate::diff::Diff
+    /// [`Diff::apply_world_mapped()`]: crate::diff::Diff::apply_world_mapped
+    pub fn map_to(&self, other: &EntityMap) -> EntityHashMap<Entity> {
+        self.iter()
+            .filter_map(|(id, entity)| Some((entity, other.entity(id)?)))
+            .collect()
+    }
+}
+
+/// Plugin maintaining the [`EntityMap`] of the app.
+///
+/// This registers the [`EntityId`] component, and updates the [`EntityMap`]
+/// resource with the entities whose [`EntityId`] was added, changed, or
+/// removed, in the [`First`] schedule.
+#[derive(Default)]
+pub struct EntityMapPlugin;
+
+impl Plugin for EntityMapPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<EntityId>()
+            .register_type::<Uuid>()
+            .init_resource::<EntityMap>()
+            .add_systems(First, sync_entity_map);
+    }
+}
+
+/// Update the [`EntityMap`] with the changes to the [`EntityId`] components.
+pub fn sync_entity_map(
+    mut map: ResMut<EntityMap>,
+    mut removed: RemovedComponents<EntityId>,
+    changed: Query<(Entity, &EntityId), Changed<EntityId>>,
+) {
+    // Removed first, in case the component was re-inserted since
+    for entity in removed.read() {
+        map.remove_entity(entity);
+    }
+    for (entity, id) in &changed {
+        map.insert(*id, entity);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn map() {
+        let (a, b) = (EntityId::new(), EntityId::new());
+        assert_ne!(a, b);
+        let (e0, e1) = (Entity::from_raw(0), Entity::from_raw(1));
+
+        let mut map = EntityMap::new();
+        map.insert(a, e0);
+        map.insert(b, e1);
+        assert_eq!(map.len(), 2);
+        assert_eq!(map.entity(a), Some(e0));
+        assert_eq!(map.id(e1), Some(b));
+
+        // Remapping an entity removes its previous identifier
+        map.insert(a, e1);
+        assert_eq!(map.len(), 1);
+        assert_eq!(map.entity(a), Some(e1));
+        assert_eq!(map.id(e0), None);
+        assert_eq!(map.entity(b), None);
+        assert!(matches!(map.resolve(b), Err(Error::UnmappedEntity(id)) if id == b));
+
+        let mut other = EntityMap::new();
+        other.insert(a, e0);
+        other.insert(b, e1);
+        let entity_map = map.map_to(&other);
+        assert_eq!(entity_map.len(), 1);
+        assert_eq!(entity_map.get(&e1), Some(&e0));
+
+        assert_eq!(map.remove_entity(e1), Some(a));
+        assert!(map.is_empty());
+    }
+
+    #[test]
+    fn sync() {
+        let mut app = App::new();
+        app.add_plugins(EntityMapPlugin);
+        let id = EntityId::new();
+        let entity = app.world.spawn(id).id();
+        let other = app.world.spawn(EntityId::new()).id();
+        app.update();
+        let map = app.world.resource::<EntityMap>();
+        assert_eq!(map.entity(id), Some(entity));
+        assert_eq!(map.len(), 2);
+        assert_eq!(
+            EntityMap::from_world(&mut app.world).entity(id),
+            Some(entity)
+        );
+
+        // Changed and removed identifiers
+        let new_id = EntityId::new();
+        app.world.entity_mut(entity).insert(new_id);
+        app.world.despawn(other);
+        app.update();
+        let map = app.world.resource::<EntityMap>();
+        assert_eq!(map.entity(id), None);
+        assert_eq!(map.entity(new_id), Some(entity));
+        assert_eq!(map.len(), 1);
+    }
+}
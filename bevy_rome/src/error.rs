@@ -1,22 +1,366 @@
-#[derive(Debug, Clone, Copy, PartialEq)]
+use bevy::ecs::entity::Entity;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+use super::entity::EntityId;
+
+/// Error produced by `bevy_rome` operations.
+#[derive(Debug)]
 pub enum Error {
-    Unknown,
+    /// An I/O error occurred on an underlying stream.
+    Io(std::io::Error),
+    /// A value failed to (de)serialize to or from RON. For syntax errors, the
+    /// position of the error in the input is recorded.
+    Ron {
+        /// The RON error.
+        error: ron::Error,
+        /// Position of the error in the input, if known.
+        position: Option<ron::error::Position>,
+    },
+    /// A value failed to (de)serialize to or from the binary format.
+    Binary(bincode::Error),
+    /// A reflect path doesn't resolve to any value.
+    InvalidPath {
+        /// The reflect path.
+        path: String,
+        /// Description of the failure.
+        reason: String,
+    },
+    /// A type is not registered in the type registry, or lacks some type data.
+    UnregisteredType(String),
+    /// A diff doesn't have a target to be applied to, or its target doesn't
+    /// designate a component.
+    NoTarget,
+    /// A diff contains changes which cannot be applied to the given target.
+    InvalidDiff,
+    /// An entity doesn't exist, or was despawned.
+    EntityNotFound(Entity),
+    /// No local entity is mapped to a stable entity identifier.
+    UnmappedEntity(EntityId),
+    /// An entity doesn't have the expected component.
+    ComponentNotFound {
+        /// The entity.
+        entity: Entity,
+        /// Type path of the component.
+        component: String,
+    },
+    /// The component type doesn't match the expected one.
+    ComponentMismatch {
+        /// Type path of the expected component.
+        expected: String,
+        /// Type path of the actual component, if any.
+        actual: Option<String>,
+    },
+    /// A world doesn't have a resource required by the operation; contains the
+    /// type name of the resource.
+    ResourceNotFound(String),
+    /// A received message is of a kind not registered with the receiver.
+    UnknownMessageKind(String),
+    /// A frame exceeds the maximum frame size.
+    FrameTooLarge {
+        /// Size of the frame, in bytes.
+        size: usize,
+        /// Maximum frame size, in bytes.
+        max: usize,
+    },
+    /// The remote end of a transport disconnected.
+    Disconnected,
+    /// The message types or reflected types registered by a remote peer don't
+    /// match the local ones.
+    SchemaMismatch {
+        /// Identifiers of the message types registered by a single peer.
+        messages: Vec<String>,
+        /// Type paths of the reflected types whose layout differs between
+        /// peers.
+        types: Vec<String>,
+    },
+    /// The protocol version of a remote peer is not compatible with the local
+    /// one.
+    VersionMismatch {
+        /// Local protocol version.
+        local: u32,
+        /// Protocol version of the remote peer.
+        remote: u32,
+    },
+    /// An object can't be made a child of another object, because that object
+    /// doesn't exist or is a descendant of the former one.
+    InvalidParent {
+        /// Identifier of the object.
+        object: EntityId,
+        /// Identifier of the invalid parent object.
+        parent: EntityId,
+    },
+    /// An edit was rejected because it conflicts with a concurrent edit
+    /// already accepted.
+    Conflict {
+        /// Identifier of the entity targetted by the edit.
+        target: EntityId,
+        /// Revision of the concurrent edit.
+        revision: u64,
+    },
+    /// An identifier is already assigned to an existing entity.
+    DuplicateEntity(EntityId),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(err) => write!(f, "I/O error: {err}"),
+            Error::Ron {
+                error,
+                position: Some(position),
+            } => write!(f, "RON error at {position}: {error}"),
+            Error::Ron {
+                error,
+                position: None,
+            } => write!(f, "RON error: {error}"),
+            Error::Binary(err) => write!(f, "binary serialization error: {err}"),
+            Error::InvalidPath { path, reason } => {
+                write!(f, "invalid reflect path '{path}': {reason}")
+            }
+            Error::UnregisteredType(type_path) => {
+                write!(
+                    f,
+                    "type '{type_path}' is not registered, or lacks some type data"
+                )
+            }
+            Error::NoTarget => write!(f, "diff has no target component"),
+            Error::InvalidDiff => write!(f, "diff cannot be applied to its target"),
+            Error::EntityNotFound(entity) => write!(f, "entity {entity:?} not found"),
+            Error::UnmappedEntity(id) => write!(f, "no entity mapped to identifier {id}"),
+            Error::ComponentNotFound { entity, component } => {
+                write!(f, "entity {entity:?} has no component '{component}'")
+            }
+            Error::ComponentMismatch {
+                expected,
+                actual: Some(actual),
+            } => write!(f, "expected component '{expected}', found '{actual}'"),
+            Error::ComponentMismatch {
+                expected,
+                actual: None,
+            } => write!(f, "expected component '{expected}', found none"),
+            Error::ResourceNotFound(resource) => write!(f, "world has no resource '{resource}'"),
+            Error::UnknownMessageKind(kind) => write!(f, "unknown message kind '{kind}'"),
+            Error::FrameTooLarge { size, max } => {
+                write!(
+                    f,
+                    "frame of {size} bytes exceeds the maximum of {max} bytes"
+                )
+            }
+            Error::Disconnected => write!(f, "remote peer disconnected"),
+            Error::SchemaMismatch { messages, types } => {
+                write!(f, "schema mismatch with remote peer")?;
+                if !messages.is_empty() {
+                    write!(
+                        f,
+                        "; messages registered by a single peer: {}",
+                        messages.join(", ")
+                    )?;
+                }
+                if !types.is_empty() {
+                    write!(f, "; types with a different layout: {}", types.join(", "))?;
+                }
+                Ok(())
+            }
+            Error::VersionMismatch { local, remote } => write!(
+                f,
+                "protocol version mismatch: local version {local}, remote version {remote}"
+            ),
+            Error::InvalidParent { object, parent } => {
+                write!(f, "object {object} can't be a child of object {parent}")
+            }
+            Error::Conflict { target, revision } => write!(
+                f,
+                "edit of entity {target} conflicts with concurrent revision {revision}"
+            ),
+            Error::DuplicateEntity(id) => write!(f, "identifier {id} is already in use"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            Error::Ron { error, .. } => Some(error),
+            Error::Binary(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+/// Serializable description of an [`Error`] which occurred on a remote peer.
+///
+/// Variants referring to a local [`Entity`] of the remote peer, or wrapping an
+/// error of another crate, are converted to [`RemoteError::Other`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RemoteError {
+    /// See [`Error::InvalidPath`].
+    InvalidPath {
+        /// The reflect path.
+        path: String,
+        /// Description of the failure.
+        reason: String,
+    },
+    /// See [`Error::UnregisteredType`].
+    UnregisteredType(String),
+    /// See [`Error::NoTarget`].
+    NoTarget,
+    /// See [`Error::InvalidDiff`].
+    InvalidDiff,
+    /// See [`Error::UnmappedEntity`].
+    UnmappedEntity(EntityId),
+    /// See [`Error::ComponentNotFound`]; contains the type path of the
+    /// component.
+    ComponentNotFound(String),
+    /// See [`Error::ComponentMismatch`].
+    ComponentMismatch {
+        /// Type path of the expected component.
+        expected: String,
+        /// Type path of the actual component, if any.
+        actual: Option<String>,
+    },
+    /// See [`Error::UnknownMessageKind`].
+    UnknownMessageKind(String),
+    /// See [`Error::Conflict`].
+    Conflict {
+        /// Identifier of the entity targetted by the edit.
+        target: EntityId,
+        /// Revision of the concurrent edit.
+        revision: u64,
+    },
+    /// Any other error, described by its message.
+    Other(String),
+}
+
+impl From<&Error> for RemoteError {
+    fn from(err: &Error) -> Self {
+        match err {
+            Error::InvalidPath { path, reason } => RemoteError::InvalidPath {
+                path: path.clone(),
+                reason: reason.clone(),
+            },
+            Error::UnregisteredType(type_path) => RemoteError::UnregisteredType(type_path.clone()),
+            Error::NoTarget => RemoteError::NoTarget,
+            Error::InvalidDiff => RemoteError::InvalidDiff,
+            Error::UnmappedEntity(id) => RemoteError::UnmappedEntity(*id),
+            Error::ComponentNotFound { component, .. } => {
+                RemoteError::ComponentNotFound(component.clone())
+            }
+            Error::ComponentMismatch { expected, actual } => RemoteError::ComponentMismatch {
+                expected: expected.clone(),
+                actual: actual.clone(),
+            },
+            Error::UnknownMessageKind(kind) => RemoteError::UnknownMessageKind(kind.clone()),
+            Error::Conflict { target, revision } => RemoteError::Conflict {
+                target: *target,
+                revision: *revision,
+            },
+            err => RemoteError::Other(err.to_string()),
+        }
+    }
 }
 
+impl fmt::Display for RemoteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RemoteError::InvalidPath { path, reason } => {
+                write!(f, "invalid reflect path '{path}': {reason}")
+            }
+            RemoteError::UnregisteredType(type_path) => {
+                write!(
+                    f,
+                    "type '{type_path}' is not registered, or lacks some type data"
+                )
+            }
+            RemoteError::NoTarget => write!(f, "diff has no target component"),
+            RemoteError::InvalidDiff => write!(f, "diff cannot be applied to its target"),
+            RemoteError::UnmappedEntity(id) => write!(f, "no entity mapped to identifier {id}"),
+            RemoteError::ComponentNotFound(component) => {
+                write!(f, "entity has no component '{component}'")
+            }
+            RemoteError::ComponentMismatch {
+                expected,
+                actual: Some(actual),
+            } => write!(f, "expected component '{expected}', found '{actual}'"),
+            RemoteError::ComponentMismatch {
+                expected,
+                actual: None,
+            } => write!(f, "expected component '{expected}', found none"),
+            RemoteError::UnknownMessageKind(kind) => write!(f, "unknown message kind '{kind}'"),
+            RemoteError::Conflict { target, revision } => write!(
+                f,
+                "edit of entity {target} conflicts with concurrent revision {revision}"
+            ),
+            RemoteError::Other(message) => f.write_str(message),
+        }
+    }
+}
+
+impl std::error::Error for RemoteError {}
+
 impl From<std::io::Error> for Error {
     fn from(err: std::io::Error) -> Self {
-        Error::Unknown // TODO
+        Error::Io(err)
     }
 }
 
 impl From<ron::Error> for Error {
     fn from(err: ron::Error) -> Self {
-        Error::Unknown // TODO
+        Error::Ron {
+            error: err,
+            position: None,
+        }
     }
 }
 
 impl From<ron::error::SpannedError> for Error {
     fn from(err: ron::error::SpannedError) -> Self {
-        Error::Unknown // TODO
+        Error::Ron {
+            error: err.code,
+            position: Some(err.position),
+        }
+    }
+}
+
+impl From<bincode::Error> for Error {
+    fn from(err: bincode::Error) -> Self {
+        Error::Binary(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::Codec;
+
+    #[test]
+    fn display() {
+        let err = Codec::Ron.decode::<(u32, u32)>(b"(1,\n  x)").unwrap_err();
+        let Error::Ron {
+            position: Some(position),
+            ..
+        } = &err
+        else {
+            panic!("Expected a RON error with position, got {err:?}");
+        };
+        assert_eq!(position.line, 2);
+        assert!(err.to_string().starts_with("RON error at 2:"));
+        assert!(std::error::Error::source(&err).is_some());
+
+        let err = Error::ComponentNotFound {
+            entity: Entity::from_raw(3),
+            component: "a::B".to_string(),
+        };
+        assert_eq!(err.to_string(), "entity 3v1 has no component 'a::B'");
+        assert!(std::error::Error::source(&err).is_none());
+
+        let remote = RemoteError::from(&err);
+        assert_eq!(remote, RemoteError::ComponentNotFound("a::B".to_string()));
+        assert_eq!(remote.to_string(), "entity has no component 'a::B'");
+        assert_eq!(
+            RemoteError::from(&Error::EntityNotFound(Entity::from_raw(3))),
+            RemoteError::Other("entity 3v1 not found".to_string())
+        );
     }
 }
@@ -0,0 +1,302 @@
+use std::io::{ErrorKind, Read, Write};
+
+use super::error::Error;
+
+/// Default maximum size in bytes of a frame, excluding its length prefix.
+pub const DEFAULT_MAX_FRAME_SIZE: usize = 16 * 1024 * 1024;
+
+/// Size in bytes of the length prefix of a frame.
+const PREFIX_SIZE: usize = std::mem::size_of::<u32>();
+
+/// Size in bytes of the chunks read at once by a [`FrameReader`].
+const READ_CHUNK_SIZE: usize = 4096;
+
+/// Incremental decoder splitting a byte stream into frames.
+///
+/// Each frame is prefixed by its length in bytes, as a little-endian `u32`.
+/// Bytes are pushed into the decoder as they're received, in chunks of any
+/// size, and complete frames are extracted with [`next_frame()`]. Incomplete
+/// frames are buffered until the rest of their bytes are pushed.
+///
+/// [`next_frame()`]: FrameDecoder::next_frame
+pub struct FrameDecoder {
+    buffer: Vec<u8>,
+    max_frame_size: usize,
+}
+
+impl Default for FrameDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FrameDecoder {
+    /// Create a new decoder with the default maximum frame size.
+    pub fn new() -> Self {
+        Self {
+            buffer: vec![],
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+        }
+    }
+
+    /// Set the maximum size in bytes of a frame.
+    pub fn with_max_frame_size(mut self, max_frame_size: usize) -> Self {
+        self.max_frame_size = max_frame_size;
+        self
+    }
+
+    /// Maximum size in bytes of a frame.
+    pub fn max_frame_size(&self) -> usize {
+        self.max_frame_size
+    }
+
+    /// Push some received bytes into the decoder.
+    pub fn push(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    /// Number of bytes buffered, not yet extracted as a frame.
+    pub fn buffered_len(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// Check if the decoder has no buffered bytes.
+    pub fn is_empty(&self) -> bool {
+        self.buffer.is_empty()
+    }
+
+    /// Extract the next complete frame, if any.
+    ///
+    /// Returns an [`Error::FrameTooLarge`] if the length prefix of the next
+    /// frame exceeds the maximum frame size. In that case the stream can't be
+    /// decoded further.
+    pub fn next_frame(&mut self) -> Result<Option<Vec<u8>>, Error> {
+        let Some(prefix) = self.buffer.get(..PREFIX_SIZE) else {
+            return Ok(None);
+        };
+        let len = u32::from_le_bytes(prefix.try_into().unwrap()) as usize;
+        if len > self.max_frame_size {
+            return Err(Error::FrameTooLarge {
+                size: len,
+                max: self.max_frame_size,
+            });
+        }
+        let end = PREFIX_SIZE + len;
+        if self.buffer.len() < end {
+            return Ok(None);
+        }
+        let frame = self.buffer[PREFIX_SIZE..end].to_vec();
+        self.buffer.drain(..end);
+        Ok(Some(frame))
+    }
+}
+
+/// Writer of length-prefixed frames to a stream.
+///
+/// See [`FrameDecoder`] for the frame format.
+pub struct FrameWriter<W: Write> {
+    writer: W,
+    max_frame_size: usize,
+}
+
+impl<W: Write> FrameWriter<W> {
+    /// Create a new frame writer with the default maximum frame size.
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+        }
+    }
+
+    /// Set the maximum size in bytes of a frame.
+    pub fn with_max_frame_size(mut self, max_frame_size: usize) -> Self {
+        self.max_frame_size = max_frame_size;
+        self
+    }
+
+    /// Write a single frame and flush the underlying writer.
+    ///
+    /// Returns an [`Error::FrameTooLarge`] without writing anything if the
+    /// frame exceeds the maximum frame size.
+    pub fn write_frame(&mut self, frame: &[u8]) -> Result<(), Error> {
+        let too_large = Error::FrameTooLarge {
+            size: frame.len(),
+            max: self.max_frame_size,
+        };
+        if frame.len() > self.max_frame_size {
+            return Err(too_large);
+        }
+        let len = u32::try_from(frame.len()).map_err(|_| too_large)?;
+        self.writer.write_all(&len.to_le_bytes())?;
+        self.writer.write_all(frame)?;
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    /// Get a reference to the underlying writer.
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    /// Unwrap the underlying writer.
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+/// Reader of length-prefixed frames from a stream.
+///
+/// See [`FrameDecoder`] for the frame format.
+pub struct FrameReader<R: Read> {
+    reader: R,
+    decoder: FrameDecoder,
+}
+
+impl<R: Read> FrameReader<R> {
+    /// Create a new frame reader with the default maximum frame size.
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            decoder: FrameDecoder::new(),
+        }
+    }
+
+    /// Set the maximum size in bytes of a frame.
+    pub fn with_max_frame_size(mut self, max_frame_size: usize) -> Self {
+        self.decoder = self.decoder.with_max_frame_size(max_frame_size);
+        self
+    }
+
+    /// Read the next frame, blocking until it's complete.
+    ///
+    /// Returns `None` once the stream ended cleanly at a frame boundary. If the
+    /// stream ends in the middle of a frame, this returns an error.
+    pub fn read_frame(&mut self) -> Result<Option<Vec<u8>>, Error> {
+        let mut chunk = [0; READ_CHUNK_SIZE];
+        loop {
+            if let Some(frame) = self.decoder.next_frame()? {
+                return Ok(Some(frame));
+            }
+            let len = match self.reader.read(&mut chunk) {
+                Ok(len) => len,
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err.into()),
+            };
+            if len == 0 {
+                if self.decoder.is_empty() {
+                    return Ok(None);
+                }
+                return Err(std::io::Error::from(ErrorKind::UnexpectedEof).into());
+            }
+            self.decoder.push(&chunk[..len]);
+        }
+    }
+
+    /// Get a reference to the underlying reader.
+    pub fn get_ref(&self) -> &R {
+        &self.reader
+    }
+
+    /// Unwrap the underlying reader, discarding any buffered byte.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Reader returning at most one byte per read.
+    struct Trickle<'a>(&'a [u8]);
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let Some((first, rest)) = self.0.split_first() else {
+                return Ok(0);
+            };
+            buf[0] = *first;
+            self.0 = rest;
+            Ok(1)
+        }
+    }
+
+    fn encode(frames: &[&[u8]]) -> Vec<u8> {
+        let mut writer = FrameWriter::new(vec![]);
+        for frame in frames {
+            writer.write_frame(frame).unwrap();
+        }
+        writer.into_inner()
+    }
+
+    #[test]
+    fn decoder() {
+        let bytes = encode(&[b"abc", b"", b"de"]);
+        assert_eq!(bytes.len(), 3 * PREFIX_SIZE + 5);
+        assert_eq!(&bytes[..PREFIX_SIZE], &[3, 0, 0, 0]);
+
+        let mut decoder = FrameDecoder::new();
+        let mut frames = vec![];
+        for byte in &bytes {
+            assert!(decoder.next_frame().unwrap().is_none());
+            decoder.push(std::slice::from_ref(byte));
+            while let Some(frame) = decoder.next_frame().unwrap() {
+                frames.push(frame);
+            }
+        }
+        assert_eq!(frames, [b"abc".to_vec(), vec![], b"de".to_vec()]);
+        assert!(decoder.is_empty());
+
+        // Back-to-back frames in a single chunk
+        decoder.push(&bytes);
+        decoder.push(&bytes[..PREFIX_SIZE + 1]);
+        assert_eq!(decoder.next_frame().unwrap().unwrap(), b"abc");
+        assert_eq!(decoder.next_frame().unwrap().unwrap(), b"");
+        assert_eq!(decoder.next_frame().unwrap().unwrap(), b"de");
+        assert!(decoder.next_frame().unwrap().is_none());
+        assert_eq!(decoder.buffered_len(), PREFIX_SIZE + 1);
+    }
+
+    #[test]
+    fn max_frame_size() {
+        let mut writer = FrameWriter::new(vec![]).with_max_frame_size(4);
+        writer.write_frame(b"1234").unwrap();
+        assert!(matches!(
+            writer.write_frame(b"12345"),
+            Err(Error::FrameTooLarge { size: 5, max: 4 })
+        ));
+        assert_eq!(writer.get_ref().len(), PREFIX_SIZE + 4);
+
+        let bytes = encode(&[b"12345"]);
+        let mut decoder = FrameDecoder::new().with_max_frame_size(4);
+        decoder.push(&bytes[..PREFIX_SIZE]);
+        assert!(matches!(
+            decoder.next_frame(),
+            Err(Error::FrameTooLarge { size: 5, max: 4 })
+        ));
+    }
+
+    #[test]
+    fn reader() {
+        let large = vec![7; 3 * READ_CHUNK_SIZE + 1];
+        let bytes = encode(&[b"abc", &large, b"de"]);
+
+        let mut reader = FrameReader::new(bytes.as_slice());
+        assert_eq!(reader.read_frame().unwrap().unwrap(), b"abc");
+        assert_eq!(reader.read_frame().unwrap().unwrap(), large);
+        assert_eq!(reader.read_frame().unwrap().unwrap(), b"de");
+        assert_eq!(reader.read_frame().unwrap(), None);
+
+        let mut reader = FrameReader::new(Trickle(&bytes));
+        assert_eq!(reader.read_frame().unwrap().unwrap(), b"abc");
+        assert_eq!(reader.read_frame().unwrap().unwrap(), large);
+        assert_eq!(reader.read_frame().unwrap().unwrap(), b"de");
+        assert_eq!(reader.read_frame().unwrap(), None);
+
+        // Truncated stream
+        let mut reader = FrameReader::new(&bytes[..bytes.len() - 1]);
+        reader.read_frame().unwrap();
+        reader.read_frame().unwrap();
+        assert!(reader.read_frame().is_err());
+    }
+}
@@ -0,0 +1,369 @@
+use bevy::reflect::{TypeInfo, TypeRegistry, VariantInfo};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::hash::Hasher;
+
+use super::codec::Codec;
+use super::error::Error;
+use super::registry::MessageRegistry;
+use super::transport::{FrameReceiver, FrameSender};
+
+/// Version of the protocol used to exchange messages.
+///
+/// This is incremented on any incompatible change to the format of the frames
+/// exchanged by peers, including the handshake itself.
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/// Description of a peer exchanged when a connection opens.
+///
+/// Each peer sends its own handshake as the first frame of the connection,
+/// then checks the one received from the remote peer with [`check()`] before
+/// accepting any message. The handshake contains the protocol version, the
+/// identifiers of the registered message types, and a digest of the layout of
+/// each registered reflected type.
+///
+/// [`check()`]: Handshake::check
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Handshake {
+    version: u32,
+    messages: BTreeSet<String>,
+    types: BTreeMap<String, u64>,
+}
+
+impl Handshake {
+    /// Create the handshake of a peer exchanging the messages registered in
+    /// `messages`, with the reflected types registered in `types`.
+    pub fn new(messages: &MessageRegistry, types: &TypeRegistry) -> Self {
+        Self {
+            version: PROTOCOL_VERSION,
+            messages: messages.ids().map(str::to_string).collect(),
+            types: types
+                .iter()
+                .map(|registration| {
+                    let info = registration.type_info();
+                    (info.type_path().to_string(), type_digest(info))
+                })
+                .collect(),
+        }
+    }
+
+    /// Protocol version of the peer.
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    /// Identifiers of the message types registered by the peer.
+    pub fn messages(&self) -> impl Iterator<Item = &str> + '_ {
+        self.messages.iter().map(String::as_str)
+    }
+
+    /// Digest of the layout of a reflected type registered by the peer, if
+    /// any.
+    ///
+    /// The digest depends on the kind of the type and on the names and type
+    /// paths of its fields and variants.
+    pub fn type_digest(&self, type_path: &str) -> Option<u64> {
+        self.types.get(type_path).copied()
+    }
+
+    /// Check if the handshake of a remote peer is compatible with this one.
+    ///
+    /// Both peers must use the same protocol version, otherwise this returns an
+    /// [`Error::VersionMismatch`]. They must also register the same message
+    /// types, and the reflected types registered by both peers must have the
+    /// same layout, otherwise this returns an [`Error::SchemaMismatch`] listing
+    /// the differences. Reflected types registered by a single peer are
+    /// ignored; they can't be exchanged anyway.
+    pub fn check(&self, remote: &Handshake) -> Result<(), Error> {
+        if self.version != remote.version {
+            return Err(Error::VersionMismatch {
+                local: self.version,
+                remote: remote.version,
+            });
+        }
+        let messages: Vec<String> = self
+            .messages
+            .symmetric_difference(&remote.messages)
+            .cloned()
+            .collect();
+        let types: Vec<String> = self
+            .types
+            .iter()
+            .filter(|(type_path, digest)| {
+                remote
+                    .types
+                    .get(*type_path)
+                    .is_some_and(|remote_digest| remote_digest != *digest)
+            })
+            .map(|(type_path, _)| type_path.clone())
+            .collect();
+        if messages.is_empty() && types.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::SchemaMismatch { messages, types })
+        }
+    }
+
+    /// Send the handshake as a single frame.
+    pub fn send<S: FrameSender + ?Sized>(&self, sender: &mut S, codec: Codec) -> Result<(), Error> {
+        sender.send_frame(&codec.encode(self)?)
+    }
+
+    /// Receive a handshake sent with [`send()`], blocking until it's available.
+    ///
+    /// Returns an [`Error::Disconnected`] if the remote peer closed the
+    /// connection before sending its handshake.
+    ///
+    /// [`send()`]: Handshake::send
+    pub fn recv<R: FrameReceiver + ?Sized>(receiver: &mut R, codec: Codec) -> Result<Self, Error> {
+        let frame = receiver.recv_frame()?.ok_or(Error::Disconnected)?;
+        codec.decode(&frame)
+    }
+}
+
+/// Spawn a background thread receiving the frames of a connection.
+///
+/// The thread first receives the handshake of the remote peer and checks it
+/// against the local `handshake`, then decodes each frame received with
+/// `decode` and passes the result to `forward`. The thread stops after the
+/// first error, including the end of the stream which is forwarded as an
+/// [`Error::Disconnected`], or once `forward` returns `false` because the
+/// results are not consumed anymore.
+pub(crate) fn spawn_receiver<T: 'static>(
+    name: String,
+    mut receiver: Box<dyn FrameReceiver>,
+    codec: Codec,
+    handshake: Handshake,
+    decode: impl Fn(&[u8]) -> Result<T, Error> + Send + 'static,
+    forward: impl Fn(Result<T, Error>) -> bool + Send + 'static,
+) -> Result<(), Error> {
+    std::thread::Builder::new().name(name).spawn(move || {
+        let accepted =
+            Handshake::recv(receiver.as_mut(), codec).and_then(|remote| handshake.check(&remote));
+        if let Err(err) = accepted {
+            forward(Err(err));
+            return;
+        }
+        loop {
+            let result = match receiver.recv_frame() {
+                Ok(Some(frame)) => decode(&frame),
+                Ok(None) => Err(Error::Disconnected),
+                Err(err) => Err(err),
+            };
+            let is_err = result.is_err();
+            if !forward(result) || is_err {
+                break;
+            }
+        }
+    })?;
+    Ok(())
+}
+
+/// Compute a digest of the layout of a type.
+///
+/// The digest must be identical across processes and builds, so doesn't use
+/// the [`TypeId`] of the types nor the default hasher of the standard library.
+///
+/// [`TypeId`]: std::any::TypeId
+fn type_digest(info: &TypeInfo) -> u64 {
+    let mut hasher = Fnv1a::default();
+    let mut write = |s: &str| {
+        hasher.write(s.as_bytes());
+        hasher.write_u8(0);
+    };
+    match info {
+        TypeInfo::Struct(info) => {
+            write("struct");
+            for field in info.iter() {
+                write(field.name());
+                write(field.type_path());
+            }
+        }
+        TypeInfo::TupleStruct(info) => {
+            write("tuple_struct");
+            info.iter().for_each(|field| write(field.type_path()));
+        }
+        TypeInfo::Tuple(info) => {
+            write("tuple");
+            info.iter().for_each(|field| write(field.type_path()));
+        }
+        TypeInfo::List(info) => {
+            write("list");
+            write(info.item_type_path_table().path());
+        }
+        TypeInfo::Array(info) => {
+            write("array");
+            write(info.item_type_path_table().path());
+            write(&info.capacity().to_string());
+        }
+        TypeInfo::Map(info) => {
+            write("map");
+            write(info.key_type_path_table().path());
+            write(info.value_type_path_table().path());
+        }
+        TypeInfo::Enum(info) => {
+            write("enum");
+            for variant in info.iter() {
+                match variant {
+                    VariantInfo::Struct(variant) => {
+                        write(variant.name());
+                        for field in variant.iter() {
+                            write(field.name());
+                            write(field.type_path());
+                        }
+                    }
+                    VariantInfo::Tuple(variant) => {
+                        write(variant.name());
+                        variant.iter().for_each(|field| write(field.type_path()));
+                    }
+                    VariantInfo::Unit(variant) => write(variant.name()),
+                }
+                // Separate variants, which may have fields with the same names
+                write("");
+            }
+        }
+        TypeInfo::Value(_) => write("value"),
+    }
+    hasher.finish()
+}
+
+/// 64-bit FNV-1a hasher, a simple hash function whose output is stable.
+struct Fnv1a(u64);
+
+impl Default for Fnv1a {
+    fn default() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Hasher for Fnv1a {
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::PosMsg;
+    use crate::transport::{ChannelTransport, Transport};
+    use crate::SetFieldMsg;
+    use bevy::reflect::{Reflect, TypePath, Typed};
+    use bevy::transform::components::Transform;
+
+    mod v1 {
+        use bevy::reflect::Reflect;
+
+        #[derive(Reflect)]
+        pub struct S {
+            pub f: f32,
+        }
+    }
+
+    mod v2 {
+        use bevy::reflect::Reflect;
+
+        #[derive(Reflect)]
+        pub struct S {
+            pub f: f64,
+        }
+    }
+
+    #[derive(Reflect)]
+    enum E {
+        A { x: u8 },
+        B(u8),
+    }
+
+    #[derive(Reflect)]
+    enum F {
+        A { x: u8, y: u8 },
+    }
+
+    fn handshake(types: impl FnOnce(&mut TypeRegistry)) -> Handshake {
+        let mut messages = MessageRegistry::new();
+        messages.register::<PosMsg, Transform>();
+        let mut registry = crate::tests::registry();
+        types(&mut registry);
+        Handshake::new(&messages, &registry)
+    }
+
+    #[test]
+    fn digest() {
+        let s = type_digest(v1::S::type_info());
+        assert_eq!(s, type_digest(v1::S::type_info()));
+        assert_ne!(s, type_digest(v2::S::type_info()));
+        assert_ne!(type_digest(E::type_info()), type_digest(F::type_info()));
+        assert_ne!(
+            type_digest(<(u8, f32)>::type_info()),
+            type_digest(<(f32, u8)>::type_info())
+        );
+
+        // Value types are only identified by their type path
+        assert_eq!(type_digest(f32::type_info()), type_digest(u8::type_info()));
+    }
+
+    #[test]
+    fn check() {
+        let local = handshake(|r| r.register::<v1::S>());
+        assert!(local.type_digest(v1::S::type_path()).is_some());
+        assert_eq!(
+            local.messages().collect::<Vec<_>>(),
+            ["bevy_rome::tests::PosMsg"]
+        );
+        local.check(&local).unwrap();
+
+        // Types registered on a single side are ignored
+        local.check(&handshake(|_| {})).unwrap();
+
+        let mut remote = local.clone();
+        remote.version += 1;
+        assert!(matches!(
+            local.check(&remote),
+            Err(Error::VersionMismatch { local: PROTOCOL_VERSION, remote })
+                if remote == PROTOCOL_VERSION + 1
+        ));
+
+        // Same type path, different layout
+        let mut remote = handshake(|r| r.register::<v2::S>());
+        remote.types.insert(
+            v1::S::type_path().to_string(),
+            remote.types[v2::S::type_path()],
+        );
+        let Err(Error::SchemaMismatch { messages, types }) = local.check(&remote) else {
+            panic!("Expected schema mismatch.");
+        };
+        assert!(messages.is_empty());
+        assert_eq!(types, [v1::S::type_path()]);
+
+        remote.messages.insert("bevy_rome::SetFieldMsg".to_string());
+        let Err(Error::SchemaMismatch { messages, .. }) = remote.check(&local) else {
+            panic!("Expected schema mismatch.");
+        };
+        assert_eq!(messages, [SetFieldMsg::type_path()]);
+    }
+
+    #[test]
+    fn exchange() {
+        let local = handshake(|_| {});
+        for codec in [Codec::Ron, Codec::Binary] {
+            let (a, b) = ChannelTransport::pair();
+            let (mut sender, _) = Box::new(a).split().unwrap();
+            let (_, mut receiver) = Box::new(b).split().unwrap();
+            local.send(&mut sender, codec).unwrap();
+            assert_eq!(Handshake::recv(&mut receiver, codec).unwrap(), local);
+            drop(sender);
+            assert!(matches!(
+                Handshake::recv(&mut receiver, codec),
+                Err(Error::Disconnected)
+            ));
+        }
+    }
+}
@@ -0,0 +1,669 @@
+use bevy::app::{App, Plugin, PostUpdate};
+use bevy::ecs::{
+    component::Component,
+    entity::Entity,
+    schedule::{IntoSystemConfigs, SystemSet},
+    system::Resource,
+    world::{Mut, World},
+};
+use bevy::log::warn;
+use bevy::reflect::TypePath;
+use bevy::utils::{Duration, Instant};
+use std::collections::VecDeque;
+
+use super::capture::DiffCaptureSet;
+use super::error::Error;
+use super::{AnyMessage, ComponentMessage, Message};
+
+/// Group of messages undone and redone together as a single history entry.
+#[derive(Default)]
+pub struct Transaction {
+    messages: Vec<(Entity, Box<dyn AnyMessage>)>,
+}
+
+impl Transaction {
+    /// Create a new empty transaction.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a message targetting an entity to the transaction.
+    pub fn push(&mut self, entity: Entity, message: Box<dyn AnyMessage>) {
+        self.messages.push((entity, message));
+    }
+
+    /// Number of messages in the transaction.
+    pub fn len(&self) -> usize {
+        self.messages.len()
+    }
+
+    /// Check if the transaction doesn't contain any message.
+    pub fn is_empty(&self) -> bool {
+        self.messages.is_empty()
+    }
+
+    /// Redo all messages in order.
+    ///
+    /// If a message fails, the messages already redone are undone before
+    /// returning the error, leaving the world unchanged.
+    fn redo(&mut self, world: &mut World) -> Result<(), Error> {
+        for index in 0..self.messages.len() {
+            let (entity, message) = &mut self.messages[index];
+            if let Err(err) = message.redo(world, *entity) {
+                for (entity, message) in self.messages[..index].iter_mut().rev() {
+                    let _ = message.undo(world, *entity);
+                }
+                return Err(err);
+            }
+        }
+        Ok(())
+    }
+
+    /// Undo all messages in reverse order.
+    ///
+    /// If a message fails, the messages already undone are redone before
+    /// returning the error, leaving the world unchanged.
+    fn undo(&mut self, world: &mut World) -> Result<(), Error> {
+        for index in (0..self.messages.len()).rev() {
+            let (entity, message) = &mut self.messages[index];
+            if let Err(err) = message.undo(world, *entity) {
+                for (entity, message) in self.messages[index + 1..].iter_mut() {
+                    let _ = message.redo(world, *entity);
+                }
+                return Err(err);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Policy for merging consecutive messages into a single history entry.
+///
+/// Merging allows continuous edits, like dragging an entity with the mouse,
+/// which produce a new message each frame, to be recorded as a single history
+/// entry undone in one step. Only messages pushed outside of any explicit
+/// transaction, targetting the same entity, and with the same message and
+/// component types can be merged; additionally the message itself needs to
+/// support merging (see [`Message::merge()`]).
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum MergePolicy {
+    /// Never merge messages; each message is recorded as its own entry.
+    #[default]
+    Never,
+    /// Merge a message if pushed within the given duration of the previous
+    /// one. The window slides with each merged message.
+    Window(Duration),
+    /// Merge messages until the history is explicitly committed with
+    /// [`UndoHistory::commit()`].
+    UntilCommit,
+}
+
+/// Pending operation of an [`UndoHistory`].
+enum HistoryOp {
+    Push {
+        transaction: Transaction,
+        time: Instant,
+        mergeable: bool,
+    },
+    Commit,
+    Undo,
+    Redo,
+}
+
+/// Undo/redo history of messages applied to a [`World`].
+///
+/// The history is a stack of [`Transaction`]s, each grouping one or more
+/// messages, with a cursor separating the applied transactions which can be
+/// undone from the undone ones which can be redone. Pushing a new transaction
+/// discards all undone transactions (the _redo branch_). The history is
+/// bounded to a maximum depth; once reached, the oldest transactions are
+/// discarded and can't be undone anymore.
+///
+/// Consecutive messages can be merged into a single entry according to the
+/// [`MergePolicy`] of the history.
+///
+/// Operations on the history ([`push()`], [`undo()`], [`redo()`]) are queued,
+/// and executed on the world by [`apply()`], typically invoked by the
+/// [`apply_undo_history()`] system added by the [`UndoPlugin`].
+///
+/// [`push()`]: UndoHistory::push
+/// [`undo()`]: UndoHistory::undo
+/// [`redo()`]: UndoHistory::redo
+/// [`apply()`]: UndoHistory::apply
+#[derive(Resource)]
+pub struct UndoHistory {
+    entries: VecDeque<Transaction>,
+    /// Number of applied entries; entries at or after this index are undone.
+    cursor: usize,
+    max_depth: usize,
+    /// Transaction being recorded, and the nesting depth of its begin/commit
+    /// calls.
+    open: Option<(Transaction, usize)>,
+    pending: VecDeque<HistoryOp>,
+    merge_policy: MergePolicy,
+    /// Can the last entry accept merging newer messages.
+    merge_open: bool,
+    /// Time the last message was pushed into the last entry.
+    last_push: Option<Instant>,
+}
+
+impl Default for UndoHistory {
+    fn default() -> Self {
+        Self::new(UndoHistory::DEFAULT_MAX_DEPTH)
+    }
+}
+
+impl UndoHistory {
+    /// Default maximum number of transactions retained.
+    pub const DEFAULT_MAX_DEPTH: usize = 100;
+
+    /// Create a new empty history retaining at most `max_depth` transactions.
+    pub fn new(max_depth: usize) -> Self {
+        Self {
+            entries: VecDeque::new(),
+            cursor: 0,
+            max_depth: max_depth.max(1),
+            open: None,
+            pending: VecDeque::new(),
+            merge_policy: MergePolicy::Never,
+            merge_open: false,
+            last_push: None,
+        }
+    }
+
+    /// Set the merge policy of the history.
+    pub fn with_merge_policy(mut self, merge_policy: MergePolicy) -> Self {
+        self.merge_policy = merge_policy;
+        self
+    }
+
+    /// Merge policy of the history.
+    pub fn merge_policy(&self) -> MergePolicy {
+        self.merge_policy
+    }
+
+    /// Set the merge policy of the history.
+    pub fn set_merge_policy(&mut self, merge_policy: MergePolicy) {
+        self.merge_policy = merge_policy;
+    }
+
+    /// Maximum number of transactions retained.
+    pub fn max_depth(&self) -> usize {
+        self.max_depth
+    }
+
+    /// Number of transactions in the history, applied or not.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Check if the history doesn't contain any transaction.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Check if an applied transaction can be undone.
+    pub fn can_undo(&self) -> bool {
+        self.cursor > 0
+    }
+
+    /// Check if an undone transaction can be redone.
+    pub fn can_redo(&self) -> bool {
+        self.cursor < self.entries.len()
+    }
+
+    /// Push a message targetting the component `T` of an entity.
+    ///
+    /// See [`push_dyn()`](UndoHistory::push_dyn) for details.
+    pub fn push<M, T>(&mut self, entity: Entity, message: M)
+    where
+        M: for<'de> Message<'de, T> + Send + Sync + 'static,
+        T: Component + TypePath,
+    {
+        self.push_dyn(entity, Box::new(ComponentMessage::<M, T>::new(message)));
+    }
+
+    /// Push a type-erased message targetting an entity.
+    ///
+    /// If a transaction is open, the message is added to it. Otherwise a new
+    /// transaction containing only that message is queued to be applied and
+    /// recorded, possibly merged into the previous entry depending on the
+    /// [`MergePolicy`].
+    pub fn push_dyn(&mut self, entity: Entity, message: Box<dyn AnyMessage>) {
+        if let Some((transaction, _)) = &mut self.open {
+            transaction.push(entity, message);
+        } else {
+            let mut transaction = Transaction::new();
+            transaction.push(entity, message);
+            self.pending.push_back(HistoryOp::Push {
+                transaction,
+                time: Instant::now(),
+                mergeable: true,
+            });
+        }
+    }
+
+    /// Begin a transaction grouping all messages pushed until the matching
+    /// [`commit_transaction()`](UndoHistory::commit_transaction).
+    ///
+    /// Transactions can be nested; nested transactions are merged into the
+    /// outermost one.
+    pub fn begin_transaction(&mut self) {
+        match &mut self.open {
+            Some((_, depth)) => *depth += 1,
+            None => self.open = Some((Transaction::new(), 1)),
+        }
+    }
+
+    /// Commit the transaction opened by the matching
+    /// [`begin_transaction()`](UndoHistory::begin_transaction).
+    ///
+    /// Once the outermost transaction is committed, it's queued to be applied
+    /// and recorded as a single history entry, unless empty.
+    pub fn commit_transaction(&mut self) {
+        let Some((_, depth)) = &mut self.open else {
+            return;
+        };
+        *depth -= 1;
+        if *depth == 0 {
+            let (transaction, _) = self.open.take().unwrap();
+            if !transaction.is_empty() {
+                self.pending.push_back(HistoryOp::Push {
+                    transaction,
+                    time: Instant::now(),
+                    mergeable: false,
+                });
+            }
+        }
+    }
+
+    /// Queue committing the last entry, preventing any newer message from
+    /// being merged into it.
+    ///
+    /// This ends a sequence of merged messages, for example at the end of a
+    /// drag operation. This is required with [`MergePolicy::UntilCommit`], and
+    /// optional with other policies.
+    pub fn commit(&mut self) {
+        self.pending.push_back(HistoryOp::Commit);
+    }
+
+    /// Queue undoing the last applied transaction, if any.
+    pub fn undo(&mut self) {
+        self.pending.push_back(HistoryOp::Undo);
+    }
+
+    /// Queue redoing the last undone transaction, if any.
+    pub fn redo(&mut self) {
+        self.pending.push_back(HistoryOp::Redo);
+    }
+
+    /// Remove all transactions from the history.
+    ///
+    /// This doesn't affect pending operations not applied yet.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.cursor = 0;
+        self.merge_open = false;
+    }
+
+    /// Execute all pending operations on the world.
+    ///
+    /// On error, the failing operation is discarded and the error returned,
+    /// leaving any remaining operation pending. A failing transaction leaves
+    /// the world unchanged.
+    pub fn apply(&mut self, world: &mut World) -> Result<(), Error> {
+        while let Some(op) = self.pending.pop_front() {
+            match op {
+                HistoryOp::Push {
+                    mut transaction,
+                    time,
+                    mergeable,
+                } => {
+                    transaction.redo(world)?;
+                    if mergeable && self.try_merge(&transaction, time) {
+                        continue;
+                    }
+                    self.entries.truncate(self.cursor);
+                    self.entries.push_back(transaction);
+                    if self.entries.len() > self.max_depth {
+                        self.entries.pop_front();
+                    }
+                    self.cursor = self.entries.len();
+                    self.merge_open = mergeable;
+                    self.last_push = Some(time);
+                }
+                HistoryOp::Commit => {
+                    self.merge_open = false;
+                }
+                HistoryOp::Undo => {
+                    self.merge_open = false;
+                    if self.cursor > 0 {
+                        self.entries[self.cursor - 1].undo(world)?;
+                        self.cursor -= 1;
+                    }
+                }
+                HistoryOp::Redo => {
+                    self.merge_open = false;
+                    if self.cursor < self.entries.len() {
+                        self.entries[self.cursor].redo(world)?;
+                        self.cursor += 1;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Try to merge an already applied single-message transaction into the
+    /// last entry.
+    fn try_merge(&mut self, transaction: &Transaction, time: Instant) -> bool {
+        if !self.merge_open || self.cursor != self.entries.len() {
+            return false;
+        }
+        let in_window = match self.merge_policy {
+            MergePolicy::Never => false,
+            MergePolicy::Window(window) => self
+                .last_push
+                .is_some_and(|last| time.saturating_duration_since(last) < window),
+            MergePolicy::UntilCommit => true,
+        };
+        if !in_window {
+            return false;
+        }
+        let Some(last) = self.entries.back_mut() else {
+            return false;
+        };
+        let ([(entity, message)], [(newer_entity, newer)]) =
+            (&mut last.messages[..], &transaction.messages[..])
+        else {
+            return false;
+        };
+        if entity != newer_entity || !message.merge(newer.as_ref()) {
+            return false;
+        }
+        self.last_push = Some(time);
+        true
+    }
+}
+
+/// System set of the [`apply_undo_history()`] system.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, SystemSet)]
+pub struct UndoSet;
+
+/// Plugin adding an [`UndoHistory`] resource and the system applying it.
+///
+/// The [`apply_undo_history()`] system runs in the [`UndoSet`] system set of
+/// the [`PostUpdate`] schedule.
+#[derive(Default)]
+pub struct UndoPlugin {
+    /// Maximum depth of the history. Zero uses the default value.
+    pub max_depth: usize,
+    /// Policy for merging consecutive messages.
+    pub merge_policy: MergePolicy,
+}
+
+impl Plugin for UndoPlugin {
+    fn build(&self, app: &mut App) {
+        let max_depth = if self.max_depth == 0 {
+            UndoHistory::DEFAULT_MAX_DEPTH
+        } else {
+            self.max_depth
+        };
+        app.insert_resource(UndoHistory::new(max_depth).with_merge_policy(self.merge_policy))
+            .add_systems(
+                PostUpdate,
+                apply_undo_history.in_set(UndoSet).before(DiffCaptureSet),
+            );
+    }
+}
+
+/// Execute the pending operations of the [`UndoHistory`] resource.
+pub fn apply_undo_history(world: &mut World) {
+    world.resource_scope(|world, mut history: Mut<UndoHistory>| {
+        while let Err(err) = history.apply(world) {
+            warn!("Failed to apply undo history operation: {:?}", err);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::PosMsg;
+    use bevy::math::Vec3;
+    use bevy::transform::components::Transform;
+
+    fn pos(x: f32) -> PosMsg {
+        PosMsg {
+            pos: Vec3::splat(x),
+        }
+    }
+
+    fn x(world: &World, entity: Entity) -> f32 {
+        world.get::<Transform>(entity).unwrap().translation.x
+    }
+
+    #[test]
+    fn undo_redo() {
+        let mut world = World::new();
+        let entity = world.spawn(Transform::default()).id();
+        let mut history = UndoHistory::default();
+        assert!(!history.can_undo());
+        assert!(!history.can_redo());
+
+        history.push(entity, pos(1.));
+        history.push(entity, pos(2.));
+        // Queued, not applied yet
+        assert_eq!(x(&world, entity), 0.);
+        history.apply(&mut world).unwrap();
+        assert_eq!(x(&world, entity), 2.);
+        assert_eq!(history.len(), 2);
+        assert!(history.can_undo());
+        assert!(!history.can_redo());
+
+        history.undo();
+        history.apply(&mut world).unwrap();
+        assert_eq!(x(&world, entity), 1.);
+        assert!(history.can_redo());
+
+        history.undo();
+        history.undo(); // no-op
+        history.apply(&mut world).unwrap();
+        assert_eq!(x(&world, entity), 0.);
+        assert!(!history.can_undo());
+
+        history.redo();
+        history.apply(&mut world).unwrap();
+        assert_eq!(x(&world, entity), 1.);
+
+        // Pushing truncates the redo branch
+        history.push(entity, pos(3.));
+        history.apply(&mut world).unwrap();
+        assert_eq!(x(&world, entity), 3.);
+        assert_eq!(history.len(), 2);
+        assert!(!history.can_redo());
+        history.redo();
+        history.apply(&mut world).unwrap();
+        assert_eq!(x(&world, entity), 3.);
+        history.undo();
+        history.undo();
+        history.apply(&mut world).unwrap();
+        assert_eq!(x(&world, entity), 0.);
+    }
+
+    #[test]
+    fn max_depth() {
+        let mut world = World::new();
+        let entity = world.spawn(Transform::default()).id();
+        let mut history = UndoHistory::new(2);
+        for i in 1..=4 {
+            history.push(entity, pos(i as f32));
+        }
+        history.apply(&mut world).unwrap();
+        assert_eq!(history.len(), 2);
+        for _ in 0..4 {
+            history.undo();
+        }
+        history.apply(&mut world).unwrap();
+        assert_eq!(x(&world, entity), 2.);
+    }
+
+    #[test]
+    fn transaction() {
+        let mut world = World::new();
+        let e1 = world.spawn(Transform::default()).id();
+        let e2 = world.spawn(Transform::default()).id();
+        let mut history = UndoHistory::default();
+        history.begin_transaction();
+        history.push(e1, pos(1.));
+        history.begin_transaction();
+        history.push(e2, pos(2.));
+        history.commit_transaction();
+        history.apply(&mut world).unwrap();
+        // Not committed yet
+        assert_eq!(x(&world, e1), 0.);
+        history.push(e1, pos(3.));
+        history.commit_transaction();
+        history.apply(&mut world).unwrap();
+        assert_eq!(x(&world, e1), 3.);
+        assert_eq!(x(&world, e2), 2.);
+        assert_eq!(history.len(), 1);
+
+        history.undo();
+        history.apply(&mut world).unwrap();
+        assert_eq!(x(&world, e1), 0.);
+        assert_eq!(x(&world, e2), 0.);
+
+        // Empty transactions are not recorded
+        history.begin_transaction();
+        history.commit_transaction();
+        history.apply(&mut world).unwrap();
+        assert_eq!(history.len(), 1);
+    }
+
+    #[test]
+    fn transaction_error() {
+        let mut world = World::new();
+        let e1 = world.spawn(Transform::default()).id();
+        let e2 = world.spawn_empty().id();
+        let mut history = UndoHistory::default();
+        history.begin_transaction();
+        history.push(e1, pos(1.));
+        history.push(e2, pos(2.));
+        history.commit_transaction();
+        history.push(e1, pos(3.));
+        assert!(matches!(
+            history.apply(&mut world),
+            Err(Error::ComponentNotFound { entity, .. }) if entity == e2
+        ));
+        assert_eq!(x(&world, e1), 0.);
+        assert!(history.is_empty());
+        history.apply(&mut world).unwrap();
+        assert_eq!(x(&world, e1), 3.);
+    }
+
+    #[test]
+    fn plugin() {
+        let mut app = App::new();
+        app.add_plugins(UndoPlugin {
+            max_depth: 8,
+            ..Default::default()
+        });
+        let entity = app.world.spawn(Transform::default()).id();
+        app.world
+            .resource_mut::<UndoHistory>()
+            .push(entity, pos(1.));
+        app.update();
+        assert_eq!(x(&app.world, entity), 1.);
+        app.world.resource_mut::<UndoHistory>().undo();
+        app.update();
+        assert_eq!(x(&app.world, entity), 0.);
+        assert_eq!(app.world.resource::<UndoHistory>().max_depth(), 8);
+    }
+
+    #[test]
+    fn merge_until_commit() {
+        let mut world = World::new();
+        let e1 = world.spawn(Transform::default()).id();
+        let e2 = world.spawn(Transform::default()).id();
+        let mut history = UndoHistory::default().with_merge_policy(MergePolicy::UntilCommit);
+
+        // Drag
+        for i in 1..=3 {
+            history.push(e1, pos(i as f32));
+        }
+        history.apply(&mut world).unwrap();
+        assert_eq!(x(&world, e1), 3.);
+        assert_eq!(history.len(), 1);
+        history.push(e1, pos(4.));
+        history.commit();
+
+        // Different entity, not merged
+        history.push(e2, pos(1.));
+        history.apply(&mut world).unwrap();
+        assert_eq!(x(&world, e1), 4.);
+        assert_eq!(history.len(), 2);
+
+        // Not merged after commit
+        history.commit();
+        history.push(e2, pos(2.));
+        history.apply(&mut world).unwrap();
+        assert_eq!(history.len(), 3);
+
+        // Not merged after undo/redo
+        history.undo();
+        history.redo();
+        history.push(e2, pos(3.));
+        history.apply(&mut world).unwrap();
+        assert_eq!(history.len(), 4);
+
+        // Transactions are never merged
+        history.commit();
+        history.begin_transaction();
+        history.push(e2, pos(4.));
+        history.commit_transaction();
+        history.push(e2, pos(5.));
+        history.apply(&mut world).unwrap();
+        assert_eq!(history.len(), 6);
+
+        for _ in 0..5 {
+            history.undo();
+        }
+        history.apply(&mut world).unwrap();
+        assert_eq!(x(&world, e1), 4.);
+        assert_eq!(x(&world, e2), 0.);
+        history.undo();
+        history.apply(&mut world).unwrap();
+        assert_eq!(x(&world, e1), 0.);
+    }
+
+    #[test]
+    fn merge_window() {
+        let mut world = World::new();
+        let entity = world.spawn(Transform::default()).id();
+
+        let mut history = UndoHistory::default();
+        history.push(entity, pos(1.));
+        history.push(entity, pos(2.));
+        history.apply(&mut world).unwrap();
+        assert_eq!(history.len(), 2);
+
+        let mut history =
+            UndoHistory::default().with_merge_policy(MergePolicy::Window(Duration::ZERO));
+        history.push(entity, pos(1.));
+        history.push(entity, pos(2.));
+        history.apply(&mut world).unwrap();
+        assert_eq!(history.len(), 2);
+
+        let mut history = UndoHistory::default()
+            .with_merge_policy(MergePolicy::Window(Duration::from_secs(3600)));
+        history.push(entity, pos(1.));
+        history.push(entity, pos(2.));
+        history.apply(&mut world).unwrap();
+        assert_eq!(history.len(), 1);
+        history.commit();
+        history.push(entity, pos(3.));
+        history.apply(&mut world).unwrap();
+        assert_eq!(history.len(), 2);
+    }
+}
@@ -0,0 +1,811 @@
+use bevy::ecs::{entity::Entity, reflect::AppTypeRegistry, world::World};
+use bevy::log::warn;
+use serde::{Deserialize, Serialize};
+use std::sync::{mpsc, Arc};
+
+use super::codec::Codec;
+use super::entity::{EntityId, EntityMap};
+use super::error::{Error, RemoteError};
+use super::handshake::{spawn_receiver, Handshake};
+use super::plugin::{EditResult, PendingEdit};
+use super::queue::{Ack, Envelope};
+use super::registry::MessageRegistry;
+use super::transport::{FrameSender, Transport};
+use super::FieldPath;
+
+/// Policy resolving conflicting edits received by a [`Hub`].
+///
+/// Two edits conflict when they're made by different clients, target the same
+/// entity, modify overlapping fields (see [`AnyMessage::fields()`]), and the
+/// client sending the latter edit had not yet received the former one.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ConflictPolicy {
+    /// Accept the latter edit, which overwrites the former one. Since the hub
+    /// orders all edits, all clients converge to the latter value.
+    #[default]
+    LastWriterWins,
+    /// Reject the latter edit with an [`Error::Conflict`]; the client which
+    /// sent it rolls it back.
+    Reject,
+}
+
+/// Conflict detected by a [`Hub`] between two edits.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Conflict {
+    /// Index of the client which sent the latter edit.
+    pub client: usize,
+    /// Sequence number of the latter edit, assigned by its client.
+    pub seq: u64,
+    /// Identifier of the entity targetted by both edits.
+    pub target: EntityId,
+    /// Revision of the former edit, already accepted by the hub.
+    pub revision: u64,
+    /// Whether the latter edit was rejected, as per the [`ConflictPolicy`].
+    pub rejected: bool,
+}
+
+/// Edit sent by a [`HubClient`] to its [`Hub`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct ClientEdit {
+    /// Latest revision received from the hub when the edit was made.
+    base: u64,
+    envelope: Envelope,
+}
+
+/// Frame sent by a [`HubClient`] to its [`Hub`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+enum ClientFrame {
+    /// Edit made by the client.
+    Edit(ClientEdit),
+    /// Acknowledgement of all the revisions of the hub up to the given one,
+    /// received by the client.
+    Received(u64),
+}
+
+/// Frame sent by a [`Hub`] to its clients.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+enum HubFrame {
+    /// Edit of another client accepted by the hub; the sequence number of the
+    /// envelope is the revision assigned by the hub.
+    Edit(Envelope),
+    /// Acknowledgement of an edit sent by the client, with the latest
+    /// revision of the hub.
+    Ack { revision: u64, ack: Ack },
+}
+
+/// Edit accepted by a [`Hub`], kept to detect conflicts.
+struct LogEntry {
+    revision: u64,
+    client: usize,
+    target: EntityId,
+    fields: Option<Vec<FieldPath>>,
+}
+
+/// Client connected to a [`Hub`].
+struct Client {
+    /// Sender to the client, or `None` once disconnected.
+    sender: Option<Box<dyn FrameSender>>,
+    /// Latest revision received by the client, from its acknowledgements or
+    /// the base of its edits.
+    received: u64,
+}
+
+/// Authoritative hub of a collaborative editing session.
+///
+/// The hub owns the reference copy of the edited world, and several
+/// [`HubClient`]s connect to it. Each edit received from a client is assigned
+/// a revision, defining a total order of all edits, applied to the world of the
+/// hub, acknowledged to its client, and forwarded to all other clients. Edits
+/// conflicting with a concurrent edit of another client are resolved with the
+/// [`ConflictPolicy`] of the hub.
+///
+/// The hub and all clients must start from the same state, for example the
+/// same scene; the hub doesn't send its world to clients joining a session.
+/// Entities are identified by their [`EntityId`], and the hub only knows the
+/// entities with an [`EntityId`] when it's created.
+pub struct Hub {
+    world: World,
+    entity_map: EntityMap,
+    registry: Arc<MessageRegistry>,
+    codec: Codec,
+    policy: ConflictPolicy,
+    clients: Vec<Client>,
+    tx: mpsc::Sender<(usize, Result<ClientFrame, Error>)>,
+    incoming: mpsc::Receiver<(usize, Result<ClientFrame, Error>)>,
+    revision: u64,
+    log: Vec<LogEntry>,
+}
+
+impl Hub {
+    /// Create a hub without any client, editing `world` with the messages
+    /// registered in `registry` serialized with `codec`.
+    ///
+    /// The world must have an [`AppTypeRegistry`] resource with all the types
+    /// exchanged by the clients.
+    pub fn new(mut world: World, registry: MessageRegistry, codec: Codec) -> Self {
+        let entity_map = EntityMap::from_world(&mut world);
+        let (tx, incoming) = mpsc::channel();
+        Self {
+            world,
+            entity_map,
+            registry: Arc::new(registry),
+            codec,
+            policy: ConflictPolicy::default(),
+            clients: vec![],
+            tx,
+            incoming,
+            revision: 0,
+            log: vec![],
+        }
+    }
+
+    /// Set the policy resolving conflicting edits.
+    pub fn with_policy(mut self, policy: ConflictPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// The world of the hub.
+    pub fn world(&self) -> &World {
+        &self.world
+    }
+
+    /// Get the entity of the world of the hub with the given identifier, if
+    /// any.
+    pub fn entity(&self, id: EntityId) -> Option<Entity> {
+        self.entity_map.entity(id)
+    }
+
+    /// Revision of the latest edit accepted, or zero if none.
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    /// Number of clients currently connected.
+    pub fn client_count(&self) -> usize {
+        self.clients
+            .iter()
+            .filter(|client| client.sender.is_some())
+            .count()
+    }
+
+    /// Connect a new client over the given transport, and return its index.
+    ///
+    /// This sends the [`Handshake`] of the hub, then spawns a background thread
+    /// checking the one of the client and receiving its edits. Edits are only
+    /// applied by [`update()`].
+    ///
+    /// [`update()`]: Hub::update
+    pub fn connect(&mut self, transport: Box<dyn Transport>) -> Result<usize, Error> {
+        let handshake = {
+            let registry = self.world.resource::<AppTypeRegistry>().read();
+            Handshake::new(&self.registry, &registry)
+        };
+        let (mut sender, receiver) = transport.split()?;
+        handshake.send(&mut sender, self.codec)?;
+        let index = self.clients.len();
+        let tx = self.tx.clone();
+        let codec = self.codec;
+        spawn_receiver(
+            format!("bevy_rome hub client #{index}"),
+            receiver,
+            codec,
+            handshake,
+            move |frame| codec.decode(frame),
+            move |result| tx.send((index, result)).is_ok(),
+        )?;
+        self.clients.push(Client {
+            sender: Some(sender),
+            received: 0,
+        });
+        Ok(index)
+    }
+
+    /// Apply all edits received since the last call, and return the conflicts
+    /// detected.
+    ///
+    /// Clients are disconnected after any error receiving their edits, or
+    /// sending them a frame.
+    pub fn update(&mut self) -> Vec<Conflict> {
+        let mut conflicts = vec![];
+        while let Ok((client, result)) = self.incoming.try_recv() {
+            match result {
+                Ok(ClientFrame::Edit(edit)) => self.apply(client, edit, &mut conflicts),
+                Ok(ClientFrame::Received(revision)) => {
+                    self.acknowledge(client, revision);
+                    self.prune_log();
+                }
+                Err(Error::Disconnected) => self.disconnect(client),
+                Err(err) => {
+                    warn!("Hub client #{} disconnected after error: {}", client, err);
+                    self.disconnect(client);
+                }
+            }
+        }
+        conflicts
+    }
+
+    /// Apply an edit received from a client.
+    fn apply(&mut self, client: usize, edit: ClientEdit, conflicts: &mut Vec<Conflict>) {
+        let ClientEdit { base, envelope } = edit;
+        self.acknowledge(client, base);
+        let result = self
+            .registry
+            .deserialize(envelope.kind(), envelope.payload(), self.codec)
+            .and_then(|mut message| {
+                let fields = message.fields();
+                if let Some(revision) =
+                    self.find_conflict(client, base, envelope.target(), fields.as_deref())
+                {
+                    let rejected = self.policy == ConflictPolicy::Reject;
+                    conflicts.push(Conflict {
+                        client,
+                        seq: envelope.seq(),
+                        target: envelope.target(),
+                        revision,
+                        rejected,
+                    });
+                    if rejected {
+                        return Err(Error::Conflict {
+                            target: envelope.target(),
+                            revision,
+                        });
+                    }
+                }
+                let entity = self.entity_map.resolve(envelope.target())?;
+                message.redo(&mut self.world, entity)?;
+                self.revision += 1;
+                self.log.push(LogEntry {
+                    revision: self.revision,
+                    client,
+                    target: envelope.target(),
+                    fields,
+                });
+                Ok(())
+            });
+        let accepted = result.is_ok();
+        let ack = Ack {
+            seq: envelope.seq(),
+            result: result.map_err(|err| RemoteError::from(&err)),
+        };
+        self.send(
+            client,
+            &HubFrame::Ack {
+                revision: self.revision,
+                ack,
+            },
+        );
+        if accepted {
+            let frame = HubFrame::Edit(Envelope::new(
+                envelope.kind(),
+                envelope.target(),
+                self.revision,
+                envelope.payload().to_vec(),
+            ));
+            for other in (0..self.clients.len()).filter(|other| *other != client) {
+                self.send(other, &frame);
+            }
+        }
+        self.prune_log();
+    }
+
+    /// Record that a client received all revisions up to `revision`.
+    fn acknowledge(&mut self, client: usize, revision: u64) {
+        let received = &mut self.clients[client].received;
+        *received = (*received).max(revision);
+    }
+
+    /// Find the latest edit accepted since revision `base` from another client
+    /// than `client`, conflicting with an edit of the given fields of `target`.
+    fn find_conflict(
+        &self,
+        client: usize,
+        base: u64,
+        target: EntityId,
+        fields: Option<&[FieldPath]>,
+    ) -> Option<u64> {
+        self.log
+            .iter()
+            .rev()
+            .take_while(|entry| entry.revision > base)
+            .find(|entry| {
+                entry.client != client
+                    && entry.target == target
+                    && match (fields, entry.fields.as_deref()) {
+                        (Some(fields), Some(other)) => fields
+                            .iter()
+                            .any(|field| other.iter().any(|other| field.overlaps(other))),
+                        _ => true,
+                    }
+            })
+            .map(|entry| entry.revision)
+    }
+
+    /// Remove the log entries which can't conflict anymore, because all
+    /// connected clients already received them.
+    fn prune_log(&mut self) {
+        let Some(base) = self
+            .clients
+            .iter()
+            .filter(|client| client.sender.is_some())
+            .map(|client| client.received)
+            .min()
+        else {
+            self.log.clear();
+            return;
+        };
+        let count = self.log.partition_point(|entry| entry.revision <= base);
+        self.log.drain(..count);
+    }
+
+    /// Send a frame to a client, disconnecting it on error.
+    fn send(&mut self, client: usize, frame: &HubFrame) {
+        let Some(sender) = self.clients[client].sender.as_mut() else {
+            return;
+        };
+        if let Err(err) = self
+            .codec
+            .encode(frame)
+            .and_then(|frame| sender.send_frame(&frame))
+        {
+            warn!("Hub client #{} disconnected after error: {}", client, err);
+            self.disconnect(client);
+        }
+    }
+
+    fn disconnect(&mut self, client: usize) {
+        self.clients[client].sender = None;
+    }
+}
+
+/// Client of a [`Hub`], editing a local copy of the world of the hub.
+///
+/// Edits sent with [`send_edit()`] are applied to the local world right away,
+/// then sent to the hub. Until the hub acknowledges them, they're _pending_.
+/// Edits of other clients forwarded by the hub are applied by [`update()`]
+/// before the pending edits, which are temporarily undone, so that the local
+/// world reflects the order defined by the hub. Pending edits rejected by the
+/// hub are rolled back.
+///
+/// Entities of the local world are identified by their [`EntityId`], and
+/// found with the [`EntityMap`] resource of the world, usually maintained by
+/// the [`EntityMapPlugin`].
+///
+/// [`send_edit()`]: HubClient::send_edit
+/// [`update()`]: HubClient::update
+/// [`EntityMapPlugin`]: crate::entity::EntityMapPlugin
+pub struct HubClient {
+    registry: Arc<MessageRegistry>,
+    codec: Codec,
+    sender: Box<dyn FrameSender>,
+    incoming: mpsc::Receiver<Result<HubFrame, Error>>,
+    next_seq: u64,
+    revision: u64,
+    /// Latest revision acknowledged to the hub.
+    acknowledged: u64,
+    /// Edits not yet acknowledged, with their sequence number.
+    pending: Vec<(u64, PendingEdit)>,
+    connected: bool,
+}
+
+impl HubClient {
+    /// Connect to a hub over the given transport, exchanging the messages
+    /// registered in `registry` serialized with `codec`.
+    ///
+    /// Like [`RomeConnection::open()`], this sends the local `handshake`, and
+    /// checks the one of the hub on a background thread before accepting any
+    /// frame.
+    ///
+    /// [`RomeConnection::open()`]: crate::plugin::RomeConnection::open
+    pub fn connect(
+        transport: Box<dyn Transport>,
+        registry: Arc<MessageRegistry>,
+        codec: Codec,
+        handshake: Handshake,
+    ) -> Result<Self, Error> {
+        let (mut sender, receiver) = transport.split()?;
+        handshake.send(&mut sender, codec)?;
+        let (tx, rx) = mpsc::channel();
+        spawn_receiver(
+            "bevy_rome hub client".to_string(),
+            receiver,
+            codec,
+            handshake,
+            move |frame| codec.decode(frame),
+            move |result| tx.send(result).is_ok(),
+        )?;
+        Ok(Self {
+            registry,
+            codec,
+            sender,
+            incoming: rx,
+            next_seq: 0,
+            revision: 0,
+            acknowledged: 0,
+            pending: vec![],
+            connected: true,
+        })
+    }
+
+    /// Check if the connection to the hub is still open.
+    pub fn is_connected(&self) -> bool {
+        self.connected
+    }
+
+    /// Latest revision of the hub known by the client.
+    ///
+    /// The client received all the edits of other clients up to that revision.
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    /// Number of edits not yet acknowledged by the hub.
+    pub fn pending_edits(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Apply an edit to the given entity of `world`, and send it to the hub.
+    ///
+    /// Returns the sequence number of the edit, reported in the
+    /// [`EditResult`] returned by [`update()`] once the hub acknowledged it.
+    /// The message type must be registered in the [`MessageRegistry`] of the
+    /// client, otherwise this returns an [`Error::UnknownMessageKind`], and the
+    /// entity must have an [`EntityId`]. Nothing is sent if applying the edit
+    /// locally fails.
+    ///
+    /// [`update()`]: HubClient::update
+    pub fn send_edit<M: Serialize + 'static>(
+        &mut self,
+        world: &mut World,
+        message: &M,
+        target: Entity,
+    ) -> Result<u64, Error> {
+        let seq = self.next_seq;
+        let base = self.revision;
+        let codec = self.codec;
+        let sender = &mut self.sender;
+        let ((), edit) = PendingEdit::apply_and_send(
+            world,
+            &self.registry,
+            codec,
+            message,
+            target,
+            |kind, id, payload| {
+                let edit = ClientEdit {
+                    base,
+                    envelope: Envelope::new(kind, id, seq, payload),
+                };
+                sender.send_frame(&codec.encode(&ClientFrame::Edit(edit))?)
+            },
+        )?;
+        self.next_seq += 1;
+        self.acknowledged = base;
+        self.pending.push((seq, edit));
+        Ok(seq)
+    }
+
+    /// Apply the frames received from the hub since the last call to `world`,
+    /// and return the result of the edits acknowledged by the hub.
+    ///
+    /// This then acknowledges the revisions received to the hub, which can
+    /// forget the edits all its clients received. Any error receiving or
+    /// decoding a frame, or sending the acknowledgement, closes the connection.
+    pub fn update(&mut self, world: &mut World) -> Vec<EditResult> {
+        let mut results = vec![];
+        loop {
+            let result = match self.incoming.try_recv() {
+                Ok(result) => result,
+                Err(mpsc::TryRecvError::Empty) => break,
+                // The background thread stops once the stream ended.
+                Err(mpsc::TryRecvError::Disconnected) => {
+                    self.connected = false;
+                    break;
+                }
+            };
+            match result {
+                Ok(HubFrame::Edit(envelope)) => self.apply_edit(world, &envelope),
+                Ok(HubFrame::Ack { revision, ack }) => {
+                    // All previous revisions were already received
+                    self.revision = revision;
+                    results.extend(self.apply_ack(world, ack));
+                }
+                Err(Error::Disconnected) => self.connected = false,
+                Err(err) => {
+                    warn!("Hub connection closed after error: {}", err);
+                    self.connected = false;
+                }
+            }
+        }
+        if self.connected && self.revision > self.acknowledged {
+            let frame = ClientFrame::Received(self.revision);
+            match self
+                .codec
+                .encode(&frame)
+                .and_then(|frame| self.sender.send_frame(&frame))
+            {
+                Ok(()) => self.acknowledged = self.revision,
+                Err(err) => {
+                    warn!("Hub connection closed after error: {}", err);
+                    self.connected = false;
+                }
+            }
+        }
+        results
+    }
+
+    /// Apply an edit of another client forwarded by the hub.
+    fn apply_edit(&mut self, world: &mut World, envelope: &Envelope) {
+        self.revision = envelope.seq();
+        self.undo_pending(world);
+        let result = self
+            .registry
+            .deserialize(envelope.kind(), envelope.payload(), self.codec)
+            .and_then(|mut message| {
+                let entity = world
+                    .get_resource::<EntityMap>()
+                    .and_then(|map| map.entity(envelope.target()))
+                    .ok_or(Error::UnmappedEntity(envelope.target()))?;
+                message.redo(world, entity)
+            });
+        if let Err(err) = result {
+            warn!(
+                "Failed to apply revision #{} to entity {}: {}",
+                envelope.seq(),
+                envelope.target(),
+                err
+            );
+        }
+        self.redo_pending(world);
+    }
+
+    /// Complete a pending edit acknowledged by the hub, rolling it back on
+    /// error.
+    fn apply_ack(&mut self, world: &mut World, ack: Ack) -> Option<EditResult> {
+        let index = self.pending.iter().position(|(seq, _)| *seq == ack.seq)?;
+        let target = self.pending[index].1.target;
+        if ack.result.is_err() {
+            // Undo the later edits too, which may depend on this one
+            self.undo_pending(world);
+            self.pending.remove(index);
+            self.redo_pending(world);
+        } else {
+            self.pending.remove(index);
+        }
+        Some(EditResult {
+            seq: ack.seq,
+            target,
+            result: ack.result,
+        })
+    }
+
+    fn undo_pending(&mut self, world: &mut World) {
+        for (seq, edit) in self.pending.iter_mut().rev() {
+            if let Err(err) = edit.message.undo(world, edit.target) {
+                warn!("Failed to undo pending edit #{}: {}", seq, err);
+            }
+        }
+    }
+
+    fn redo_pending(&mut self, world: &mut World) {
+        for (seq, edit) in &mut self.pending {
+            if let Err(err) = edit.message.redo(world, edit.target) {
+                warn!("Failed to redo pending edit #{}: {}", seq, err);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::wait_until;
+    use crate::transport::ChannelTransport;
+    use crate::SetFieldMsg;
+    use bevy::math::Vec3;
+    use bevy::transform::components::Transform;
+
+    fn messages() -> MessageRegistry {
+        let mut messages = MessageRegistry::new();
+        messages.register_any::<SetFieldMsg>();
+        messages
+    }
+
+    /// Create a world with a single entity.
+    fn world(id: EntityId) -> World {
+        let mut world = crate::tests::world();
+        world.spawn((Transform::default(), id));
+        let map = EntityMap::from_world(&mut world);
+        world.insert_resource(map);
+        world
+    }
+
+    struct Session {
+        hub: Hub,
+        clients: Vec<(HubClient, World)>,
+        id: EntityId,
+        entity: Entity,
+    }
+
+    impl Session {
+        fn new(policy: ConflictPolicy) -> Self {
+            let id = EntityId::new();
+            let mut hub = Hub::new(world(id), messages(), Codec::Ron).with_policy(policy);
+            let registry = Arc::new(messages());
+            let clients = (0..2)
+                .map(|_| {
+                    let (local, remote) = ChannelTransport::pair();
+                    hub.connect(Box::new(remote)).unwrap();
+                    let world = world(id);
+                    let handshake =
+                        Handshake::new(&registry, &world.resource::<AppTypeRegistry>().read());
+                    let client = HubClient::connect(
+                        Box::new(local),
+                        registry.clone(),
+                        Codec::Ron,
+                        handshake,
+                    )
+                    .unwrap();
+                    (client, world)
+                })
+                .collect();
+            let entity = hub.entity(id).unwrap();
+            Self {
+                hub,
+                clients,
+                id,
+                entity,
+            }
+        }
+
+        /// Send an edit of the translation of the entity from a client.
+        fn edit(&mut self, client: usize, field: &str, value: f32) -> u64 {
+            let (client, world) = &mut self.clients[client];
+            let registry = world.resource::<AppTypeRegistry>().clone();
+            let msg =
+                SetFieldMsg::of::<Transform>(field, &value, &registry.read(), Codec::Ron).unwrap();
+            // Same entity index in all worlds
+            client.send_edit(world, &msg, self.entity).unwrap()
+        }
+
+        /// Update the hub until it accepted or rejected `count` edits in total.
+        fn update_hub(&mut self, count: usize, conflicts: &mut Vec<Conflict>) {
+            wait_until(|| {
+                conflicts.extend(self.hub.update());
+                let rejected = conflicts.iter().filter(|c| c.rejected).count();
+                self.hub.revision() as usize + rejected == count
+            });
+        }
+
+        /// Update the clients until they have no pending edit and received all
+        /// revisions of the hub.
+        fn sync(&mut self) -> Vec<Vec<EditResult>> {
+            let mut results = vec![vec![]; self.clients.len()];
+            wait_until(|| {
+                let mut synced = true;
+                for (index, (client, world)) in self.clients.iter_mut().enumerate() {
+                    results[index].extend(client.update(world));
+                    synced &=
+                        client.pending_edits() == 0 && client.revision() == self.hub.revision();
+                }
+                synced
+            });
+            results
+        }
+
+        /// Update the hub until it received the acknowledgements of all the
+        /// revisions by all clients.
+        fn acknowledge(&mut self) {
+            wait_until(|| {
+                assert!(self.hub.update().is_empty());
+                self.hub.log.is_empty()
+            });
+        }
+
+        fn translation(&self, client: Option<usize>) -> Vec3 {
+            let world = match client {
+                Some(client) => &self.clients[client].1,
+                None => self.hub.world(),
+            };
+            world.get::<Transform>(self.entity).unwrap().translation
+        }
+    }
+
+    #[test]
+    fn last_writer_wins() {
+        let mut session = Session::new(ConflictPolicy::LastWriterWins);
+        assert_eq!(session.hub.client_count(), 2);
+
+        // Concurrent edits of the same field; the hub receives client #0 first
+        let mut conflicts = vec![];
+        session.edit(0, ".translation.x", 1.);
+        session.update_hub(1, &mut conflicts);
+        let seq = session.edit(1, ".translation.x", 2.);
+        session.update_hub(2, &mut conflicts);
+        assert_eq!(
+            conflicts,
+            [Conflict {
+                client: 1,
+                seq,
+                target: session.id,
+                revision: 1,
+                rejected: false,
+            }]
+        );
+
+        let results = session.sync();
+        assert!(results.iter().flatten().all(|result| result.result.is_ok()));
+        for client in [None, Some(0), Some(1)] {
+            assert_eq!(session.translation(client).x, 2.);
+        }
+
+        // Concurrent edits of different fields don't conflict
+        session.edit(0, ".translation.y", 3.);
+        session.edit(1, ".translation.z", 4.);
+        let mut conflicts = vec![];
+        session.update_hub(4, &mut conflicts);
+        assert!(conflicts.is_empty());
+        session.sync();
+        for client in [None, Some(0), Some(1)] {
+            assert_eq!(session.translation(client), Vec3::new(2., 3., 4.));
+        }
+    }
+
+    #[test]
+    fn reject() {
+        let mut session = Session::new(ConflictPolicy::Reject);
+        let mut conflicts = vec![];
+        session.edit(0, ".translation.x", 1.);
+        session.update_hub(1, &mut conflicts);
+        let seq = session.edit(1, ".translation.x", 2.);
+        // Later edit applied after the rejected one
+        let later = session.edit(1, ".scale.x", 5.);
+        assert_eq!(session.translation(Some(1)).x, 2.);
+        session.update_hub(3, &mut conflicts);
+        assert_eq!(conflicts.len(), 1);
+        assert!(conflicts[0].rejected);
+
+        let results = session.sync();
+        assert_eq!(
+            results[1],
+            [
+                EditResult {
+                    seq,
+                    target: session.entity,
+                    result: Err(RemoteError::Conflict {
+                        target: session.id,
+                        revision: 1
+                    })
+                },
+                EditResult {
+                    seq: later,
+                    target: session.entity,
+                    result: Ok(())
+                }
+            ]
+        );
+        for client in [None, Some(0), Some(1)] {
+            assert_eq!(session.translation(client), Vec3::new(1., 0., 0.));
+            let world = match client {
+                Some(client) => &session.clients[client].1,
+                None => session.hub.world(),
+            };
+            assert_eq!(world.get::<Transform>(session.entity).unwrap().scale.x, 5.);
+        }
+    }
+
+    #[test]
+    fn prune_log() {
+        let mut session = Session::new(ConflictPolicy::LastWriterWins);
+        let mut conflicts = vec![];
+        // Client #1 only watches the edits of client #0
+        for count in 1..=10 {
+            session.edit(0, ".translation.x", count as f32);
+            session.update_hub(count, &mut conflicts);
+            assert!(session.hub.log.len() <= 1);
+            session.sync();
+            session.acknowledge();
+        }
+        assert!(conflicts.is_empty());
+        assert_eq!(session.translation(Some(1)).x, 10.);
+    }
+}
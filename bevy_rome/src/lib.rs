@@ -1,103 +1,602 @@
-#![allow(dead_code, unused_imports, unused_variables, unused_mut)] // temp
-
 use bevy::ecs::{
-    component::{Component, ComponentId},
+    component::Component,
     entity::Entity,
+    reflect::AppTypeRegistry,
     world::{Mut, World},
 };
-use bevy::math::Vec3;
-use bevy::transform::components::Transform;
-use serde::{Deserialize, Serialize, Serializer};
-use std::any::{Any, TypeId};
-use std::io::prelude::*;
-use std::net::{TcpListener, TcpStream};
-
-mod diff;
+use bevy::reflect::{Reflect, TypePath, TypeRegistry};
+use serde::{Deserialize, Serialize};
+use std::any::{type_name, Any};
+use std::marker::PhantomData;
+
+pub mod capture;
+pub mod codec;
+pub mod diff;
+pub mod entity;
 mod error;
+pub mod framing;
+pub mod handshake;
+pub mod history;
+pub mod hub;
+pub mod mirror;
+pub mod plugin;
+pub mod queue;
+pub mod registry;
+pub mod scene;
+pub mod snapshot;
+pub mod transport;
+pub mod truth;
 
-use error::Error;
+pub use error::{Error, RemoteError};
 
-trait Message<'de, T>: Serialize + Deserialize<'de> {
+use codec::Codec;
+use diff::{Diff, DiffContent, DiffData, DiffTarget};
+
+/// Undoable change applied to a target of type `T`.
+///
+/// Messages are serializable to be sent to other processes. A message is
+/// first applied with [`redo()`], and can then be reverted with [`undo()`].
+/// Messages typically swap their content with the target value, so that
+/// applying the same message repeatedly toggles the target between its old and
+/// new values.
+///
+/// [`redo()`]: Message::redo
+/// [`undo()`]: Message::undo
+pub trait Message<'de, T>: Serialize + Deserialize<'de> {
+    /// Revert the change previously applied by [`redo()`](Message::redo).
     fn undo(&mut self, target: &mut T);
+    /// Apply the change to the target.
     fn redo(&mut self, target: &mut T);
+
+    /// Merge a newer message into this one.
+    ///
+    /// The `newer` message was applied right after this one, and both were
+    /// already applied to the target. If the messages can be merged, such that
+    /// undoing this message alone reverts the changes of both messages, then
+    /// this method updates this message accordingly and returns `true`;
+    /// otherwise it returns `false`.
+    ///
+    /// Messages swapping their content with the target can merge by simply
+    /// returning `true`, because after being applied this message already
+    /// contains the value of the target before both changes.
+    ///
+    /// The default implementation doesn't support merging and returns `false`.
+    fn merge(&mut self, newer: &Self) -> bool {
+        let _ = newer;
+        false
+    }
 }
 
-#[derive(Serialize, Deserialize)]
-struct PosMsg {
-    pos: Vec3,
+/// Type-erased message applied to an entity of a [`World`].
+///
+/// This allows storing and applying heterogeneous messages. A [`Message`]
+/// modifying a component can be converted to an [`AnyMessage`] with
+/// [`ComponentMessage`].
+pub trait AnyMessage: Send + Sync + 'static {
+    /// Revert the change previously applied by
+    /// [`redo()`](AnyMessage::redo) to the target entity.
+    fn undo(&mut self, world: &mut World, entity: Entity) -> Result<(), Error>;
+    /// Apply the change to the target entity.
+    fn redo(&mut self, world: &mut World, entity: Entity) -> Result<(), Error>;
+
+    /// Merge a newer message targetting the same entity into this one.
+    ///
+    /// See [`Message::merge()`] for details. The default implementation doesn't
+    /// support merging and returns `false`.
+    fn merge(&mut self, newer: &dyn AnyMessage) -> bool {
+        let _ = newer;
+        false
+    }
+
+    /// Fields of the target entity modified by the message, if known.
+    ///
+    /// This is used to detect conflicting edits, which modify overlapping
+    /// fields. The default implementation returns `None`, meaning the message
+    /// may modify anything on the target entity.
+    fn fields(&self) -> Option<Vec<FieldPath>> {
+        None
+    }
+
+    /// Get the message as [`Any`], for downcasting.
+    fn as_any(&self) -> &dyn Any;
 }
 
-impl Message<'_, Transform> for PosMsg {
-    fn undo(&mut self, target: &mut Transform) {
-        std::mem::swap(&mut target.translation, &mut self.pos);
+/// Field of a component, identified by the type path of the component and a
+/// reflect path relative to it.
+///
+/// An empty reflect path designates the whole component.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct FieldPath {
+    /// Type path of the component.
+    pub component: String,
+    /// Reflect path of the field, relative to the component.
+    pub path: String,
+}
+
+impl FieldPath {
+    /// Create a new path to the field at `path` of the component with the
+    /// given type path.
+    pub fn new(component: impl Into<String>, path: impl Into<String>) -> Self {
+        Self {
+            component: component.into(),
+            path: path.into(),
+        }
     }
-    fn redo(&mut self, target: &mut Transform) {
-        std::mem::swap(&mut target.translation, &mut self.pos);
+
+    /// Create a new path to the field at `path` of the component `T`.
+    pub fn of<T: Component + TypePath>(path: impl Into<String>) -> Self {
+        Self::new(T::type_path(), path)
     }
+
+    /// Check if two fields overlap, that is they're the same field or one
+    /// contains the other.
+    pub fn overlaps(&self, other: &FieldPath) -> bool {
+        fn contains(outer: &str, inner: &str) -> bool {
+            inner
+                .strip_prefix(outer)
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with(['.', '[', '#']))
+        }
+        self.component == other.component
+            && (contains(&self.path, &other.path) || contains(&other.path, &self.path))
+    }
+}
+
+/// Adapter applying a [`Message`] to the component `T` of an entity.
+pub struct ComponentMessage<M, T> {
+    message: M,
+    marker: PhantomData<fn(&mut T)>,
 }
 
-struct SendQueue<W: Write> {
-    serializer: ron::ser::Serializer<W>,
+impl<M, T> ComponentMessage<M, T> {
+    /// Wrap a message targetting the component `T`.
+    pub fn new(message: M) -> Self {
+        Self {
+            message,
+            marker: PhantomData,
+        }
+    }
+
+    /// Unwrap the message.
+    pub fn into_inner(self) -> M {
+        self.message
+    }
+
+    fn component_mut(world: &mut World, entity: Entity) -> Result<Mut<'_, T>, Error>
+    where
+        T: Component + TypePath,
+    {
+        if world.get_entity(entity).is_none() {
+            return Err(Error::EntityNotFound(entity));
+        }
+        world
+            .get_mut::<T>(entity)
+            .ok_or_else(|| Error::ComponentNotFound {
+                entity,
+                component: T::type_path().to_string(),
+            })
+    }
 }
 
-impl<W: Write> SendQueue<W> {
-    pub fn new(writer: W) -> Self {
-        let serializer = ron::ser::Serializer::new(writer, None).unwrap();
-        Self { serializer }
+impl<M, T> AnyMessage for ComponentMessage<M, T>
+where
+    M: for<'de> Message<'de, T> + Send + Sync + 'static,
+    T: Component + TypePath,
+{
+    fn undo(&mut self, world: &mut World, entity: Entity) -> Result<(), Error> {
+        let mut target = Self::component_mut(world, entity)?;
+        self.message.undo(&mut target);
+        Ok(())
     }
 
-    pub fn send<'de, T>(&mut self, msg: impl Message<'de, T>, target: Entity) -> Result<(), Error> {
-        msg.serialize(&mut self.serializer)?;
+    fn redo(&mut self, world: &mut World, entity: Entity) -> Result<(), Error> {
+        let mut target = Self::component_mut(world, entity)?;
+        self.message.redo(&mut target);
         Ok(())
     }
+
+    fn merge(&mut self, newer: &dyn AnyMessage) -> bool {
+        newer
+            .as_any()
+            .downcast_ref::<Self>()
+            .is_some_and(|newer| self.message.merge(&newer.message))
+    }
+
+    fn fields(&self) -> Option<Vec<FieldPath>> {
+        Some(vec![FieldPath::of::<T>("")])
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// Message setting the value of a field of any reflected component.
+///
+/// The component is identified by its type path, and the field by a reflect
+/// path relative to the component; an empty path designates the whole
+/// component. The component type must be registered with its
+/// [`ReflectComponent`] type data in the [`AppTypeRegistry`] of the world the
+/// message is applied to.
+///
+/// Like other messages, the value is swapped with the one of the field when
+/// applied, so the message can be undone.
+///
+/// [`ReflectComponent`]: bevy::ecs::reflect::ReflectComponent
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, TypePath)]
+pub struct SetFieldMsg {
+    component: String,
+    value: DiffData,
+}
+
+impl SetFieldMsg {
+    /// Create a message setting the field at `path` of the component with the
+    /// given type path to `value`.
+    ///
+    /// The value is serialized with `codec`, usually the one of the connection
+    /// the message is sent over.
+    pub fn new(
+        component: impl Into<String>,
+        path: impl Into<String>,
+        value: &dyn Reflect,
+        registry: &TypeRegistry,
+        codec: Codec,
+    ) -> Result<Self, Error> {
+        Ok(Self {
+            component: component.into(),
+            value: DiffData::new(path.into(), value, registry, codec)?,
+        })
+    }
+
+    /// Create a message setting the field at `path` of the component `T` to
+    /// `value`, serialized with `codec`.
+    pub fn of<T: Component + TypePath>(
+        path: impl Into<String>,
+        value: &dyn Reflect,
+        registry: &TypeRegistry,
+        codec: Codec,
+    ) -> Result<Self, Error> {
+        Self::new(T::type_path(), path, value, registry, codec)
+    }
+
+    /// Type path of the target component.
+    pub fn component(&self) -> &str {
+        &self.component
+    }
+
+    /// Reflect path of the target field, relative to the component.
+    pub fn path(&self) -> &str {
+        self.value.path()
+    }
+
+    /// Swap the value of the message with the one of the target field.
+    fn swap(&mut self, world: &mut World, entity: Entity) -> Result<(), Error> {
+        let registry = world
+            .get_resource::<AppTypeRegistry>()
+            .ok_or_else(|| Error::ResourceNotFound(type_name::<AppTypeRegistry>().to_string()))?
+            .clone();
+        let registry = registry.read();
+        let target = DiffTarget::new(entity, self.component.as_str());
+        let mut component = target.resolve_reflect_mut(world, &registry)?;
+        self.value = self.value.replace(component.as_reflect_mut(), &registry)?;
+        Ok(())
+    }
+}
+
+impl AnyMessage for SetFieldMsg {
+    fn undo(&mut self, world: &mut World, entity: Entity) -> Result<(), Error> {
+        self.swap(world, entity)
+    }
+
+    fn redo(&mut self, world: &mut World, entity: Entity) -> Result<(), Error> {
+        self.swap(world, entity)
+    }
+
+    fn merge(&mut self, newer: &dyn AnyMessage) -> bool {
+        newer
+            .as_any()
+            .downcast_ref::<Self>()
+            .is_some_and(|newer| newer.component == self.component && newer.path() == self.path())
+    }
+
+    fn fields(&self) -> Option<Vec<FieldPath>> {
+        Some(vec![FieldPath::new(self.component.as_str(), self.path())])
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
 }
 
-struct RecvQueue {}
+/// Message applying a two-way [`Diff`] to a component of any entity.
+///
+/// The entity targetted by the diff is ignored; the diff is applied to the same
+/// component of the entity the message is applied to, so a diff made in one
+/// world can be sent to another. The diff is inverted to undo the message. Like
+/// for [`SetFieldMsg`], the component type must be registered in the
+/// [`AppTypeRegistry`] of the world the message is applied to.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, TypePath)]
+pub struct DiffMsg {
+    diff: Diff,
+}
 
-impl RecvQueue {
-    pub fn new() -> Self {
-        Self {}
+impl DiffMsg {
+    /// Create a message applying the given diff.
+    ///
+    /// The diff must target a component, otherwise this returns an
+    /// [`Error::NoTarget`], and must be two-way, otherwise this returns an
+    /// [`Error::InvalidDiff`].
+    pub fn new(diff: Diff) -> Result<Self, Error> {
+        if diff.target().and_then(DiffTarget::component).is_none() {
+            return Err(Error::NoTarget);
+        }
+        if !diff.is_two_way() {
+            return Err(Error::InvalidDiff);
+        }
+        Ok(Self { diff })
     }
 
-    pub fn recv<'de, T, M: Message<'de, T>>(&mut self, bytes: &'de [u8]) -> Result<M, Error> {
-        ron::de::from_bytes::<M>(bytes).map_err(Into::into)
+    /// The diff applied by the message.
+    pub fn diff(&self) -> &Diff {
+        &self.diff
+    }
+
+    /// Apply a diff made from the diff of the message to the component of an
+    /// entity.
+    fn apply(diff: &Diff, world: &mut World, entity: Entity) -> Result<(), Error> {
+        let target = diff.target().ok_or(Error::NoTarget)?.with_entity(entity);
+        let registry = world
+            .get_resource::<AppTypeRegistry>()
+            .ok_or_else(|| Error::ResourceNotFound(type_name::<AppTypeRegistry>().to_string()))?
+            .clone();
+        let registry = registry.read();
+        diff.clone()
+            .with_target(target)
+            .apply_world(world, &registry)
+    }
+}
+
+impl AnyMessage for DiffMsg {
+    fn undo(&mut self, world: &mut World, entity: Entity) -> Result<(), Error> {
+        let inverse = self.diff.invert().ok_or(Error::InvalidDiff)?;
+        Self::apply(&inverse, world, entity)
+    }
+
+    fn redo(&mut self, world: &mut World, entity: Entity) -> Result<(), Error> {
+        Self::apply(&self.diff, world, entity)
+    }
+
+    fn fields(&self) -> Option<Vec<FieldPath>> {
+        let component = self.diff.target()?.component()?;
+        self.diff
+            .content()
+            .iter()
+            .map(|content| match content {
+                DiffContent::Single(data) | DiffContent::Dual(_, data) => {
+                    Some(FieldPath::new(component, data.path()))
+                }
+                DiffContent::Insert(_) | DiffContent::Remove(_) => {
+                    Some(FieldPath::new(component, ""))
+                }
+                DiffContent::Spawn | DiffContent::Despawn => None,
+            })
+            .collect()
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use bevy::math::{Quat, Vec3};
+    use bevy::transform::components::Transform;
+    use bevy::utils::{Duration, Instant};
+
+    /// Message setting the translation of a [`Transform`], used as a typed
+    /// message in tests.
+    #[derive(Serialize, Deserialize, TypePath)]
+    pub(crate) struct PosMsg {
+        pub pos: Vec3,
+    }
+
+    impl Message<'_, Transform> for PosMsg {
+        fn undo(&mut self, target: &mut Transform) {
+            std::mem::swap(&mut target.translation, &mut self.pos);
+        }
+        fn redo(&mut self, target: &mut Transform) {
+            std::mem::swap(&mut target.translation, &mut self.pos);
+        }
+        fn merge(&mut self, _newer: &Self) -> bool {
+            true
+        }
+    }
+
+    /// Create a type registry with [`Transform`] and the types of its fields.
+    pub(crate) fn registry() -> TypeRegistry {
+        let mut registry = TypeRegistry::new();
+        registry.register::<Transform>();
+        registry.register::<Vec3>();
+        registry.register::<Quat>();
+        registry.register::<f32>();
+        registry
+    }
+
+    /// Create an empty world, whose [`AppTypeRegistry`] contains the types of
+    /// [`registry()`].
+    pub(crate) fn world() -> World {
+        let mut world = World::new();
+        world.init_resource::<AppTypeRegistry>();
+        *world.resource::<AppTypeRegistry>().write() = registry();
+        world
+    }
+
+    /// Poll until the condition holds, or panic after a timeout.
+    ///
+    /// Used to wait for frames sent over a transport, received by a background
+    /// thread.
+    pub(crate) fn wait_until(mut condition: impl FnMut() -> bool) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !condition() {
+            assert!(Instant::now() < deadline, "Condition not met.");
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
 
     #[test]
-    fn name() -> Result<(), Error> {
-        std::thread::spawn(|| {
-            let listener = TcpListener::bind("127.0.0.1:34254").unwrap();
-            let mut stream = match listener.accept() {
-                Ok((stream, addr)) => {
-                    println!("new client: {addr:?}");
-                    stream
-                }
-                Err(e) => {
-                    println!("couldn't get client: {e:?}");
-                    return;
-                }
-            };
-            let mut queue = RecvQueue::new();
-            let mut buf: [u8; 1024] = [0; 1024];
-            let len = stream.read(&mut buf).unwrap();
-
-            // TODO - need to decode the message type first!!!!
-
-            // let msg = queue.recv(&buf[..len]);
-        });
-
-        let stream = TcpStream::connect("127.0.0.1:34254")?;
-        let mut queue = SendQueue::new(stream);
-        let msg = PosMsg {
-            pos: Vec3::splat(1.),
-        };
-        let target = Entity::from_raw(42);
-        queue.send(msg, target)?;
-        Ok(())
+    fn set_field() {
+        let mut world = world();
+        let entity = world.spawn(Transform::default()).id();
+        let registry = world.resource::<AppTypeRegistry>().clone();
+        let registry = registry.read();
+
+        let mut msg =
+            SetFieldMsg::of::<Transform>(".translation", &Vec3::splat(1.), &registry, Codec::Ron)
+                .unwrap();
+        assert_eq!(msg.component(), Transform::type_path());
+        assert_eq!(msg.path(), ".translation");
+        msg.redo(&mut world, entity).unwrap();
+        assert_eq!(
+            world.get::<Transform>(entity).unwrap().translation,
+            Vec3::splat(1.)
+        );
+        msg.undo(&mut world, entity).unwrap();
+        assert_eq!(
+            world.get::<Transform>(entity).unwrap().translation,
+            Vec3::ZERO
+        );
+
+        // Nested field serialized in binary, and whole component
+        let mut msg =
+            SetFieldMsg::of::<Transform>(".scale.y", &3_f32, &registry, Codec::Binary).unwrap();
+        msg.redo(&mut world, entity).unwrap();
+        assert_eq!(
+            world.get::<Transform>(entity).unwrap().scale,
+            Vec3::new(1., 3., 1.)
+        );
+        let mut msg =
+            SetFieldMsg::of::<Transform>("", &Transform::IDENTITY, &registry, Codec::Ron).unwrap();
+        msg.redo(&mut world, entity).unwrap();
+        assert_eq!(
+            *world.get::<Transform>(entity).unwrap(),
+            Transform::IDENTITY
+        );
+        msg.undo(&mut world, entity).unwrap();
+        assert_eq!(world.get::<Transform>(entity).unwrap().scale.y, 3.);
+
+        // Errors leave the component unchanged
+        let mut msg =
+            SetFieldMsg::of::<Transform>(".translation", &1_f32, &registry, Codec::Ron).unwrap();
+        assert!(msg.redo(&mut world, entity).is_err());
+        let mut msg =
+            SetFieldMsg::of::<Transform>(".invalid", &1_f32, &registry, Codec::Ron).unwrap();
+        assert!(matches!(
+            msg.redo(&mut world, entity),
+            Err(Error::InvalidPath { path, .. }) if path == ".invalid"
+        ));
+        let mut msg = SetFieldMsg::new("unknown::Type", "", &1_f32, &registry, Codec::Ron).unwrap();
+        assert!(matches!(
+            msg.redo(&mut world, entity),
+            Err(Error::UnregisteredType(type_path)) if type_path == "unknown::Type"
+        ));
+        let other = world.spawn_empty().id();
+        let mut msg =
+            SetFieldMsg::of::<Transform>(".scale.y", &1_f32, &registry, Codec::Ron).unwrap();
+        assert!(matches!(
+            msg.redo(&mut world, other),
+            Err(Error::ComponentNotFound { entity, component })
+                if entity == other && component == Transform::type_path()
+        ));
+        assert_eq!(world.get::<Transform>(entity).unwrap().scale.y, 3.);
+        let mut bare = World::new();
+        let entity = bare.spawn(Transform::IDENTITY).id();
+        assert!(matches!(
+            msg.redo(&mut bare, entity),
+            Err(Error::ResourceNotFound(resource)) if resource == type_name::<AppTypeRegistry>()
+        ));
+    }
+
+    #[test]
+    fn diff_msg() {
+        let mut world = world();
+        let entity = world.spawn(Transform::default()).id();
+        let registry = world.resource::<AppTypeRegistry>().clone();
+        let registry = registry.read();
+
+        let base = Transform::default();
+        let curr = Transform::from_xyz(1., 2., 3.);
+        let diff = Diff::make(&base, &curr, &registry).unwrap();
+        let target = DiffTarget::of::<Transform>(Entity::PLACEHOLDER);
+        assert!(matches!(DiffMsg::new(diff.clone()), Err(Error::NoTarget)));
+        assert!(matches!(
+            DiffMsg::new(diff.with_target(target.clone())),
+            Err(Error::InvalidDiff)
+        ));
+
+        // The target entity of the diff is replaced
+        let diff = Diff::make_two_way(&base, &curr, &registry).unwrap();
+        let mut msg = DiffMsg::new(diff.with_target(target)).unwrap();
+        msg.redo(&mut world, entity).unwrap();
+        assert_eq!(*world.get::<Transform>(entity).unwrap(), curr);
+        msg.undo(&mut world, entity).unwrap();
+        assert_eq!(*world.get::<Transform>(entity).unwrap(), base);
+    }
+
+    #[test]
+    fn field_path() {
+        let translation = FieldPath::of::<Transform>(".translation");
+        assert!(translation.overlaps(&translation));
+        assert!(translation.overlaps(&FieldPath::of::<Transform>(".translation.x")));
+        assert!(FieldPath::of::<Transform>("").overlaps(&translation));
+        assert!(!translation.overlaps(&FieldPath::of::<Transform>(".translation_2")));
+        assert!(!translation.overlaps(&FieldPath::of::<Transform>(".scale")));
+        assert!(!translation.overlaps(&FieldPath::new("a::B", ".translation")));
+
+        let msg = SetFieldMsg::of::<Transform>(
+            ".scale.y",
+            &3_f32,
+            &world().resource::<AppTypeRegistry>().read(),
+            Codec::Ron,
+        )
+        .unwrap();
+        assert_eq!(
+            msg.fields(),
+            Some(vec![FieldPath::of::<Transform>(".scale.y")])
+        );
+        let msg = ComponentMessage::<_, Transform>::new(PosMsg { pos: Vec3::ONE });
+        assert_eq!(msg.fields(), Some(vec![FieldPath::of::<Transform>("")]));
+    }
+
+    #[test]
+    fn set_field_merge() {
+        let mut world = world();
+        let entity = world.spawn(Transform::default()).id();
+        let registry = world.resource::<AppTypeRegistry>().clone();
+        let registry = registry.read();
+        let mut history =
+            history::UndoHistory::default().with_merge_policy(history::MergePolicy::UntilCommit);
+
+        for i in 1..=3 {
+            let value = Vec3::splat(i as f32);
+            let msg = SetFieldMsg::of::<Transform>(".translation", &value, &registry, Codec::Ron)
+                .unwrap();
+            history.push_dyn(entity, Box::new(msg));
+        }
+        let msg = SetFieldMsg::of::<Transform>(".scale.x", &2_f32, &registry, Codec::Ron).unwrap();
+        history.push_dyn(entity, Box::new(msg));
+        history.apply(&mut world).unwrap();
+        assert_eq!(history.len(), 2);
+        let transform = world.get::<Transform>(entity).unwrap();
+        assert_eq!(transform.translation, Vec3::splat(3.));
+        assert_eq!(transform.scale.x, 2.);
+
+        history.undo();
+        history.undo();
+        history.apply(&mut world).unwrap();
+        assert_eq!(
+            *world.get::<Transform>(entity).unwrap(),
+            Transform::default()
+        );
     }
 }
@@ -0,0 +1,610 @@
+use bevy::app::{App, Plugin, PreUpdate};
+use bevy::ecs::{
+    entity::{Entity, EntityHashMap, EntityHashSet},
+    event::{Events, ManualEventReader},
+    query::{Changed, QueryState},
+    reflect::{AppTypeRegistry, ReflectComponent},
+    removal_detection::RemovedComponents,
+    schedule::{IntoSystemConfigs, SystemSet},
+    system::{Resource, SystemState},
+    world::{Mut, World},
+};
+use bevy::log::warn;
+use bevy::reflect::TypeRegistry;
+use serde::{Deserialize, Serialize};
+use std::sync::{mpsc, Mutex};
+
+use super::capture::{DiffCapturePlugin, DiffCaptureSet, DiffEvent, ReflectDiffCapture};
+use super::codec::Codec;
+use super::diff::{Diff, DiffContent, DiffData, DiffTarget};
+use super::entity::{EntityId, EntityMap};
+use super::error::Error;
+use super::handshake::{spawn_receiver, Handshake};
+use super::registry::MessageRegistry;
+use super::transport::{FrameSender, Transport};
+
+/// Diff of an entity identified by its [`EntityId`].
+///
+/// The entity of the [`DiffTarget`] of the diff is the one of the world the
+/// diff was made from, and is meaningless for the receiver, which replaces it
+/// with its own entity having the same [`EntityId`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EntityDiff {
+    /// Identifier of the entity.
+    pub id: EntityId,
+    /// Diff of the entity, or of one of its components.
+    pub diff: Diff,
+}
+
+/// Update streamed by a [`MirrorSource`] to a [`MirrorWorld`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum MirrorUpdate {
+    /// Full state of the mirrored entities, replacing any previous state.
+    Snapshot(Vec<EntityDiff>),
+    /// Changes since the previous update.
+    Diffs(Vec<EntityDiff>),
+}
+
+/// Source of the updates streamed to connected [`MirrorWorld`]s.
+///
+/// Only entities with an [`EntityId`] are mirrored, and only their components
+/// registered with the [`ReflectDiffCapture`] type data. When a peer connects,
+/// it's first sent a [`Handshake`], then a [`MirrorUpdate::Snapshot`] of all
+/// mirrored entities. After that, it's sent once per update a
+/// [`MirrorUpdate::Diffs`] with the diffs captured by the [`DiffCapturePlugin`]
+/// during that update, if any.
+#[derive(Resource)]
+pub struct MirrorSource {
+    codec: Codec,
+    peers: Mutex<Vec<Box<dyn FrameSender>>>,
+    pending: Mutex<Vec<Box<dyn FrameSender>>>,
+    /// Identifier of each mirrored entity.
+    known: EntityHashMap<EntityId>,
+    reader: ManualEventReader<DiffEvent>,
+    identified: Option<QueryState<(Entity, &'static EntityId), Changed<EntityId>>>,
+    removed: Option<SystemState<RemovedComponents<'static, 'static, EntityId>>>,
+}
+
+impl MirrorSource {
+    /// Create a source without any peer, streaming updates serialized with
+    /// `codec`.
+    pub fn new(codec: Codec) -> Self {
+        Self {
+            codec,
+            peers: Mutex::default(),
+            pending: Mutex::default(),
+            known: EntityHashMap::default(),
+            reader: ManualEventReader::default(),
+            identified: None,
+            removed: None,
+        }
+    }
+
+    /// Connect a new peer over the given transport.
+    ///
+    /// The peer is sent its handshake and snapshot at the next update. The
+    /// receiving half of the transport is unused, and dropped.
+    pub fn connect(&mut self, transport: Box<dyn Transport>) -> Result<(), Error> {
+        let (sender, _) = transport.split()?;
+        self.pending.get_mut().unwrap().push(sender);
+        Ok(())
+    }
+
+    /// Number of connected peers, including the ones not sent their snapshot
+    /// yet.
+    pub fn peer_count(&self) -> usize {
+        self.peers.lock().unwrap().len() + self.pending.lock().unwrap().len()
+    }
+
+    fn stream(&mut self, world: &mut World, registry: &TypeRegistry) -> Result<(), Error> {
+        let codec = self.codec;
+        let captured: Vec<Diff> = self
+            .reader
+            .read(world.resource::<Events<DiffEvent>>())
+            .map(|event| event.0.clone())
+            .collect();
+        let mut diffs = vec![];
+
+        // Newly identified entities are sent whole
+        let mut fresh = EntityHashSet::default();
+        let identified = self
+            .identified
+            .get_or_insert_with(|| world.query_filtered());
+        for (entity, id) in identified.iter(world) {
+            match self.known.insert(entity, *id) {
+                Some(old_id) if old_id == *id => continue,
+                Some(old_id) => diffs.push(despawn(entity, old_id)),
+                None => {}
+            }
+            diffs.extend(entity_snapshot(world, entity, *id, registry, codec)?);
+            fresh.insert(entity);
+        }
+
+        for diff in captured {
+            let Some(target) = diff.target() else {
+                continue;
+            };
+            if fresh.contains(&target.entity()) {
+                continue;
+            }
+            if let Some(id) = self.known.get(&target.entity()) {
+                diffs.push(EntityDiff { id: *id, diff });
+            }
+        }
+
+        // Despawned entities, or which lost their identifier
+        let removed: Vec<Entity> = self
+            .removed
+            .get_or_insert_with(|| SystemState::new(world))
+            .get(world)
+            .read()
+            .collect();
+        for entity in removed {
+            let Some(&id) = self.known.get(&entity) else {
+                continue;
+            };
+            if world.get::<EntityId>(entity) != Some(&id) {
+                self.known.remove(&entity);
+                diffs.push(despawn(entity, id));
+            }
+        }
+
+        if !diffs.is_empty() {
+            let frame = codec.encode(&MirrorUpdate::Diffs(diffs))?;
+            self.peers
+                .get_mut()
+                .unwrap()
+                .retain_mut(|peer| send(peer.as_mut(), &frame));
+        }
+
+        let pending = std::mem::take(self.pending.get_mut().unwrap());
+        if !pending.is_empty() {
+            let handshake = codec.encode(&Handshake::new(&MessageRegistry::new(), registry))?;
+            let mut snapshot = vec![];
+            for (entity, id) in &self.known {
+                snapshot.extend(entity_snapshot(world, *entity, *id, registry, codec)?);
+            }
+            let snapshot = codec.encode(&MirrorUpdate::Snapshot(snapshot))?;
+            for mut peer in pending {
+                if send(peer.as_mut(), &handshake) && send(peer.as_mut(), &snapshot) {
+                    self.peers.get_mut().unwrap().push(peer);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Send a frame to a peer, and return `false` if it failed.
+fn send(peer: &mut dyn FrameSender, frame: &[u8]) -> bool {
+    match peer.send_frame(frame) {
+        Ok(()) => true,
+        Err(err) => {
+            warn!("Disconnecting mirror peer after error: {}", err);
+            false
+        }
+    }
+}
+
+fn despawn(entity: Entity, id: EntityId) -> EntityDiff {
+    EntityDiff {
+        id,
+        diff: Diff::new(DiffTarget::from_entity(entity), vec![DiffContent::Despawn]),
+    }
+}
+
+/// Make the diffs inserting all the mirrored components of an entity.
+fn entity_snapshot(
+    world: &World,
+    entity: Entity,
+    id: EntityId,
+    registry: &TypeRegistry,
+    codec: Codec,
+) -> Result<Vec<EntityDiff>, Error> {
+    let entity_ref = world.entity(entity);
+    let mut diffs = vec![EntityDiff {
+        id,
+        diff: Diff::new(DiffTarget::from_entity(entity), vec![DiffContent::Spawn]),
+    }];
+    for registration in registry.iter() {
+        if registration.data::<ReflectDiffCapture>().is_none() {
+            continue;
+        }
+        let Some(value) = registration
+            .data::<ReflectComponent>()
+            .and_then(|reflect_component| reflect_component.reflect(entity_ref))
+        else {
+            continue;
+        };
+        let data = DiffData::new(String::new(), value, registry, codec)?;
+        let target = DiffTarget::new(entity, registration.type_info().type_path());
+        diffs.push(EntityDiff {
+            id,
+            diff: Diff::new(target, vec![DiffContent::Insert(data)]),
+        });
+    }
+    Ok(diffs)
+}
+
+/// Plugin streaming the mirrored entities of the app to connected
+/// [`MirrorWorld`]s.
+///
+/// This inserts a [`MirrorSource`] resource, to which peers are connected with
+/// [`MirrorSource::connect()`], and adds the [`DiffCapturePlugin`] if needed.
+/// Updates are streamed in the schedule of the [`DiffCapturePlugin`], after the
+/// [`DiffCaptureSet`]. To capture diffs in another schedule than the default
+/// [`PostUpdate`] one, add the [`DiffCapturePlugin`] before this plugin.
+///
+/// [`PostUpdate`]: bevy::app::PostUpdate
+#[derive(Default)]
+pub struct MirrorSourcePlugin {
+    /// Serialization format of the updates.
+    pub codec: Codec,
+}
+
+impl Plugin for MirrorSourcePlugin {
+    fn build(&self, app: &mut App) {
+        if !app.is_plugin_added::<DiffCapturePlugin>() {
+            app.add_plugins(DiffCapturePlugin::default());
+        }
+        let schedule = app.get_added_plugins::<DiffCapturePlugin>()[0].schedule;
+        app.insert_resource(MirrorSource::new(self.codec))
+            .add_systems(schedule, stream_mirror.after(DiffCaptureSet));
+    }
+}
+
+/// Stream the changes of this update to the peers of the [`MirrorSource`].
+pub fn stream_mirror(world: &mut World) {
+    let registry = world.resource::<AppTypeRegistry>().clone();
+    let registry = registry.read();
+    world.resource_scope(|world, mut source: Mut<MirrorSource>| {
+        if let Err(err) = source.stream(world, &registry) {
+            warn!("Failed to stream mirror updates: {}", err);
+        }
+    });
+}
+
+/// Read-only copy of the mirrored entities of a remote [`World`].
+///
+/// The mirror world contains one entity per mirrored entity of the remote
+/// world, with the same [`EntityId`], and a copy of its mirrored components.
+/// It's updated by applying the [`MirrorUpdate`]s streamed by the remote
+/// [`MirrorSource`]. The component types must be registered in the type
+/// registry of the mirror world.
+#[derive(Resource)]
+pub struct MirrorWorld {
+    world: World,
+    entity_map: EntityMap,
+}
+
+impl MirrorWorld {
+    /// Create an empty mirror world, using the given type registry.
+    pub fn new(registry: AppTypeRegistry) -> Self {
+        let mut world = World::new();
+        world.insert_resource(registry);
+        Self {
+            world,
+            entity_map: EntityMap::new(),
+        }
+    }
+
+    /// The mirror world.
+    pub fn world(&self) -> &World {
+        &self.world
+    }
+
+    /// Get the entity of the mirror world with the given identifier, if any.
+    pub fn entity(&self, id: EntityId) -> Option<Entity> {
+        self.entity_map.entity(id)
+    }
+
+    /// Apply an update streamed by a [`MirrorSource`].
+    ///
+    /// Diffs are applied in order, and the first error aborts, leaving the
+    /// mirror world partially updated.
+    pub fn apply(&mut self, update: &MirrorUpdate) -> Result<(), Error> {
+        let diffs = match update {
+            MirrorUpdate::Snapshot(diffs) => {
+                self.world.clear_entities();
+                self.entity_map.clear();
+                diffs
+            }
+            MirrorUpdate::Diffs(diffs) => diffs,
+        };
+        let registry = self.world.resource::<AppTypeRegistry>().clone();
+        let registry = registry.read();
+        for EntityDiff { id, diff } in diffs {
+            let target = diff.target().ok_or(Error::NoTarget)?;
+            match diff.content() {
+                [DiffContent::Spawn] => {
+                    if let Some(entity) = self.entity_map.remove_id(*id) {
+                        self.world.despawn(entity);
+                    }
+                    let entity = self.world.spawn(*id).id();
+                    self.entity_map.insert(*id, entity);
+                }
+                [DiffContent::Despawn] => {
+                    let entity = self.entity_map.resolve(*id)?;
+                    self.world.despawn(entity);
+                    self.entity_map.remove_id(*id);
+                }
+                _ => {
+                    let entity = self.entity_map.resolve(*id)?;
+                    diff.clone()
+                        .with_target(target.with_entity(entity))
+                        .apply_world(&mut self.world, &registry)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Plugin maintaining a [`MirrorWorld`] resource from the updates streamed by
+/// a remote [`MirrorSourcePlugin`] over a [`Transport`].
+///
+/// Updates are received on a background thread, after checking the
+/// [`Handshake`] of the remote peer, and applied in [`PreUpdate`] in the
+/// [`MirrorSet`] system set. Any error closes the connection; the mirror world
+/// then keeps its last state.
+///
+/// # Panics
+///
+/// Finishing the plugin panics if the connection can't be opened, or if the
+/// plugin is finished more than once.
+pub struct MirrorPlugin {
+    transport: Mutex<Option<Box<dyn Transport>>>,
+    codec: Codec,
+}
+
+impl MirrorPlugin {
+    /// Create a plugin mirroring the world of the remote peer of `transport`.
+    pub fn new(transport: impl Transport) -> Self {
+        Self {
+            transport: Mutex::new(Some(Box::new(transport))),
+            codec: Codec::Ron,
+        }
+    }
+
+    /// Set the serialization format of the updates, which must match the one
+    /// of the remote [`MirrorSourcePlugin`].
+    pub fn with_codec(mut self, codec: Codec) -> Self {
+        self.codec = codec;
+        self
+    }
+}
+
+/// System set applying the [`MirrorUpdate`]s received by the [`MirrorPlugin`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, SystemSet)]
+pub struct MirrorSet;
+
+/// Connection of a [`MirrorPlugin`] to a remote [`MirrorSource`].
+#[derive(Resource)]
+pub struct MirrorConnection {
+    incoming: Mutex<mpsc::Receiver<Result<MirrorUpdate, Error>>>,
+    connected: bool,
+}
+
+impl MirrorConnection {
+    /// Check if the connection is still open.
+    pub fn is_connected(&self) -> bool {
+        self.connected
+    }
+}
+
+impl Plugin for MirrorPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(PreUpdate, apply_mirror_updates.in_set(MirrorSet));
+    }
+
+    fn finish(&self, app: &mut App) {
+        let transport = self
+            .transport
+            .lock()
+            .unwrap()
+            .take()
+            .expect("MirrorPlugin can only be finished once.");
+        let registry = app.world.resource::<AppTypeRegistry>().clone();
+        let handshake = Handshake::new(&MessageRegistry::new(), &registry.read());
+        let codec = self.codec;
+        let (tx, rx) = mpsc::channel();
+        transport
+            .split()
+            .and_then(|(_, receiver)| {
+                spawn_receiver(
+                    "bevy_rome mirror".to_string(),
+                    receiver,
+                    codec,
+                    handshake,
+                    move |frame| codec.decode(frame),
+                    move |result| tx.send(result).is_ok(),
+                )
+            })
+            .unwrap_or_else(|err| panic!("Failed to open mirror connection: {err}"));
+        app.insert_resource(MirrorWorld::new(registry))
+            .insert_resource(MirrorConnection {
+                incoming: Mutex::new(rx),
+                connected: true,
+            });
+    }
+}
+
+/// Apply all updates received since the last call to the [`MirrorWorld`].
+pub fn apply_mirror_updates(world: &mut World) {
+    world.resource_scope(|world, mut connection: Mut<MirrorConnection>| {
+        let connection = connection.as_mut();
+        let incoming = connection.incoming.get_mut().unwrap();
+        let mut mirror = world.resource_mut::<MirrorWorld>();
+        loop {
+            let result = match incoming.try_recv() {
+                Ok(result) => result,
+                Err(mpsc::TryRecvError::Empty) => break,
+                // The background thread stops once the stream ended.
+                Err(mpsc::TryRecvError::Disconnected) => {
+                    connection.connected = false;
+                    break;
+                }
+            };
+            match result.and_then(|update| mirror.apply(&update)) {
+                Ok(()) => {}
+                Err(Error::Disconnected) => connection.connected = false,
+                Err(err) => {
+                    warn!("Mirror connection closed after error: {}", err);
+                    connection.connected = false;
+                }
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::wait_until;
+    use crate::transport::ChannelTransport;
+    use bevy::app::{PostUpdate, Update};
+    use bevy::ecs::{component::Component, schedule::ScheduleLabel};
+    use bevy::reflect::Reflect;
+
+    #[derive(Debug, Clone, PartialEq, Component, Reflect)]
+    #[reflect(Component, DiffCapture)]
+    struct C {
+        f: f32,
+        i: i32,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Component, Reflect)]
+    #[reflect(Component)]
+    struct NotMirrored(u32);
+
+    fn game() -> App {
+        let mut app = App::new();
+        app.add_plugins(MirrorSourcePlugin::default())
+            .register_type::<C>()
+            .register_type::<NotMirrored>();
+        app
+    }
+
+    fn editor(transport: ChannelTransport) -> App {
+        let mut app = App::new();
+        app.add_plugins(MirrorPlugin::new(transport))
+            .register_type::<C>()
+            .register_type::<NotMirrored>();
+        app.finish();
+        app
+    }
+
+    /// Update both apps until the condition holds on the mirror world, or
+    /// panic after a while.
+    fn sync(game: &mut App, editor: &mut App, condition: impl Fn(&MirrorWorld) -> bool) {
+        wait_until(|| {
+            game.update();
+            editor.update();
+            condition(editor.world.resource::<MirrorWorld>())
+        });
+    }
+
+    fn mirrored(mirror: &MirrorWorld, id: EntityId) -> Option<&C> {
+        mirror.world().get::<C>(mirror.entity(id)?)
+    }
+
+    #[test]
+    fn mirror() {
+        let mut game = game();
+        let a = EntityId::new();
+        let b = EntityId::new();
+        let ea = game
+            .world
+            .spawn((a, C { f: 1., i: 1 }, NotMirrored(0)))
+            .id();
+        let eb = game.world.spawn((b, C { f: 2., i: 2 })).id();
+        game.world.spawn(C { f: 3., i: 3 });
+        game.update();
+
+        // The editor connects after the entities were spawned
+        let (local, remote) = ChannelTransport::pair();
+        let mut source = game.world.resource_mut::<MirrorSource>();
+        source.connect(Box::new(remote)).unwrap();
+        assert_eq!(source.peer_count(), 1);
+        let mut editor = editor(local);
+        sync(&mut game, &mut editor, |mirror| {
+            mirrored(mirror, a).is_some() && mirrored(mirror, b).is_some()
+        });
+        let mirror = editor.world.resource::<MirrorWorld>();
+        assert_eq!(mirror.world().entities().len(), 2);
+        let entity = mirror.entity(a).unwrap();
+        assert_eq!(mirror.world().get::<EntityId>(entity), Some(&a));
+        assert!(mirror.world().get::<NotMirrored>(entity).is_none());
+
+        // Incremental changes
+        game.world.get_mut::<C>(ea).unwrap().f = 5.;
+        game.world.despawn(eb);
+        let c = EntityId::new();
+        game.world.spawn((c, C { f: 6., i: 6 }));
+        sync(&mut game, &mut editor, |mirror| {
+            mirrored(mirror, a).is_some_and(|value| value.f == 5.)
+                && mirror.entity(b).is_none()
+                && mirrored(mirror, c) == Some(&C { f: 6., i: 6 })
+        });
+        assert!(editor.world.resource::<MirrorConnection>().is_connected());
+
+        game.world.entity_mut(ea).remove::<C>();
+        sync(&mut game, &mut editor, |mirror| {
+            mirror.entity(a).is_some() && mirrored(mirror, a).is_none()
+        });
+
+        // Disconnecting keeps the last state
+        drop(game);
+        wait_until(|| {
+            editor.update();
+            !editor.world.resource::<MirrorConnection>().is_connected()
+        });
+        let mirror = editor.world.resource::<MirrorWorld>();
+        assert_eq!(mirrored(mirror, c), Some(&C { f: 6., i: 6 }));
+    }
+
+    #[test]
+    fn apply() {
+        let registry = AppTypeRegistry::default();
+        registry.write().register::<C>();
+        let mut mirror = MirrorWorld::new(registry.clone());
+        let id = EntityId::new();
+        let mut world = World::new();
+        let entity = world.spawn(C { f: 1., i: 1 }).id();
+        let snapshot = entity_snapshot(&world, entity, id, &registry.read(), Codec::Binary);
+        mirror
+            .apply(&MirrorUpdate::Snapshot(snapshot.unwrap()))
+            .unwrap();
+        assert_eq!(mirrored(&mirror, id), Some(&C { f: 1., i: 1 }));
+
+        // Snapshots replace the previous state
+        mirror.apply(&MirrorUpdate::Snapshot(vec![])).unwrap();
+        assert!(mirror.entity(id).is_none());
+        assert_eq!(mirror.world().entities().len(), 0);
+
+        let update = MirrorUpdate::Diffs(vec![despawn(entity, id)]);
+        assert!(matches!(
+            mirror.apply(&update),
+            Err(Error::UnmappedEntity(unmapped)) if unmapped == id
+        ));
+    }
+
+    #[test]
+    fn capture_schedule() {
+        let mut app = App::new();
+        app.add_plugins((
+            DiffCapturePlugin::new(Update),
+            MirrorSourcePlugin::default(),
+        ));
+        let streams = |app: &App, schedule| {
+            app.get_schedule(schedule).is_some_and(|schedule| {
+                schedule
+                    .graph()
+                    .systems()
+                    .any(|(_, system, _)| system.name().ends_with("stream_mirror"))
+            })
+        };
+        assert!(streams(&app, Update.intern()));
+        assert!(!streams(&app, PostUpdate.intern()));
+    }
+}
@@ -0,0 +1,671 @@
+use bevy::app::{App, Plugin, PostUpdate, PreUpdate};
+use bevy::ecs::{
+    entity::Entity,
+    event::{Event, EventReader, EventWriter},
+    query::QueryEntityError,
+    reflect::AppTypeRegistry,
+    schedule::{IntoSystemConfigs, SystemSet},
+    system::{Query, Res, ResMut, Resource, SystemParam},
+    world::{Mut, World},
+};
+use bevy::log::warn;
+use bevy::reflect::TypePath;
+use bevy::utils::HashMap;
+use serde::Serialize;
+use std::sync::{mpsc, Arc, Mutex};
+
+use super::codec::Codec;
+use super::entity::{EntityId, EntityMap, EntityMapPlugin};
+use super::error::{Error, RemoteError};
+use super::handshake::{spawn_receiver, Handshake};
+use super::queue::{decode_frame, Ack, Received, ReceivedMessage, SendQueue};
+use super::registry::MessageRegistry;
+use super::transport::{FrameSender, Transport};
+use super::AnyMessage;
+
+/// System sets of the [`RomePlugin`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, SystemSet)]
+pub enum RomeSet {
+    /// Apply the messages received since the last update, in [`PreUpdate`].
+    Receive,
+    /// Send the messages queued with [`RomeSender`] during this update, in
+    /// [`PostUpdate`].
+    Send,
+}
+
+/// Event for a message queued with [`RomeSender`], not yet sent.
+#[derive(Debug, Clone, Event)]
+pub struct OutgoingMessage {
+    kind: String,
+    payload: Vec<u8>,
+    target: EntityId,
+}
+
+/// Event sent once the remote peer acknowledged an edit sent with
+/// [`RomeConnection::send_edit()`].
+#[derive(Debug, Clone, PartialEq, Event)]
+pub struct EditResult {
+    /// Sequence number of the edit message.
+    pub seq: u64,
+    /// Local entity targetted by the edit.
+    pub target: Entity,
+    /// Result of applying the edit on the remote peer. On error, the edit was
+    /// already rolled back locally.
+    pub result: Result<(), RemoteError>,
+}
+
+/// Edit sent to a remote peer and applied locally, not yet acknowledged.
+pub(crate) struct PendingEdit {
+    /// Local entity targetted by the edit.
+    pub target: Entity,
+    /// Copy of the message applied locally, which keeps the state to undo it.
+    pub message: Box<dyn AnyMessage>,
+}
+
+impl PendingEdit {
+    /// Apply an edit to the `target` entity of `world`, then send it with
+    /// `send`, which receives the kind of the message, the [`EntityId`] of the
+    /// target entity, and the serialized message.
+    ///
+    /// The message type must be registered in `registry`, and the target entity
+    /// must have an [`EntityId`]. Nothing is sent if applying the edit locally
+    /// fails, and the edit is rolled back if sending it fails.
+    pub fn apply_and_send<M: Serialize + 'static, T>(
+        world: &mut World,
+        registry: &MessageRegistry,
+        codec: Codec,
+        message: &M,
+        target: Entity,
+        send: impl FnOnce(&str, EntityId, Vec<u8>) -> Result<T, Error>,
+    ) -> Result<(T, Self), Error> {
+        let id = *world
+            .get_entity(target)
+            .ok_or(Error::EntityNotFound(target))?
+            .get::<EntityId>()
+            .ok_or_else(|| Error::ComponentNotFound {
+                entity: target,
+                component: EntityId::type_path().to_string(),
+            })?;
+        let kind = registry
+            .id::<M>()
+            .ok_or_else(|| Error::UnknownMessageKind(std::any::type_name::<M>().to_string()))?;
+        let payload = codec.encode(message)?;
+        // Apply a copy of the message, which keeps the state to undo it
+        let mut local = registry.deserialize(kind, &payload, codec)?;
+        local.redo(world, target)?;
+        match send(kind, id, payload) {
+            Ok(value) => Ok((
+                value,
+                Self {
+                    target,
+                    message: local,
+                },
+            )),
+            Err(err) => {
+                if let Err(undo_err) = local.undo(world, target) {
+                    warn!("Failed to roll back edit of entity {target:?}: {undo_err}");
+                }
+                Err(err)
+            }
+        }
+    }
+}
+
+/// Connection to a remote peer, exchanging messages over a [`Transport`].
+///
+/// When the connection opens, both peers exchange their [`Handshake`], and
+/// messages are only accepted once the handshake of the remote peer was checked
+/// to be compatible with the local one. Incoming messages are received on a
+/// background thread, and buffered until applied to the world by
+/// [`apply_received_messages()`]. Any error during the handshake, or receiving
+/// or decoding a message, closes the connection.
+///
+/// Each message applied is acknowledged to the remote peer with the result of
+/// applying it. Edits sent with [`send_edit()`] are applied locally without
+/// waiting for that acknowledgement, and rolled back if the remote peer failed
+/// to apply them.
+///
+/// [`send_edit()`]: RomeConnection::send_edit
+#[derive(Resource)]
+pub struct RomeConnection {
+    registry: Arc<MessageRegistry>,
+    codec: Codec,
+    send_queue: Mutex<SendQueue<Box<dyn FrameSender>>>,
+    incoming: Mutex<mpsc::Receiver<Result<Received, Error>>>,
+    pending: HashMap<u64, PendingEdit>,
+    connected: bool,
+}
+
+impl RomeConnection {
+    /// Open a connection over the given transport, exchanging the messages
+    /// registered in `registry` serialized with `codec`.
+    ///
+    /// This sends the local `handshake` to the remote peer, then spawns the
+    /// background thread receiving and checking the remote handshake, then
+    /// receiving messages. The `handshake` is usually created from `registry`
+    /// and the [`AppTypeRegistry`] of the app.
+    pub fn open(
+        transport: Box<dyn Transport>,
+        registry: Arc<MessageRegistry>,
+        codec: Codec,
+        handshake: Handshake,
+    ) -> Result<Self, Error> {
+        let (mut sender, receiver) = transport.split()?;
+        handshake.send(&mut sender, codec)?;
+        let (tx, rx) = mpsc::channel();
+        let recv_registry = registry.clone();
+        spawn_receiver(
+            "bevy_rome receiver".to_string(),
+            receiver,
+            codec,
+            handshake,
+            move |frame| decode_frame(frame, &recv_registry, codec),
+            move |result| tx.send(result).is_ok(),
+        )?;
+        Ok(Self {
+            registry: registry.clone(),
+            codec,
+            send_queue: Mutex::new(SendQueue::new(sender, registry).with_codec(codec)),
+            incoming: Mutex::new(rx),
+            pending: HashMap::default(),
+            connected: true,
+        })
+    }
+
+    /// Check if the connection is still open.
+    ///
+    /// The connection is closed once the remote peer disconnects, or after any
+    /// error. Messages received before that are still applied.
+    pub fn is_connected(&self) -> bool {
+        self.connected
+    }
+
+    /// Registry of the messages exchanged over the connection.
+    pub fn registry(&self) -> &Arc<MessageRegistry> {
+        &self.registry
+    }
+
+    /// Serialization format of the messages exchanged over the connection.
+    pub fn codec(&self) -> Codec {
+        self.codec
+    }
+
+    /// Number of edits sent with [`send_edit()`] not yet acknowledged by the
+    /// remote peer.
+    ///
+    /// [`send_edit()`]: RomeConnection::send_edit
+    pub fn pending_edits(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Apply an edit to the given entity, and send it to the remote peer.
+    ///
+    /// The edit is a message applied locally right away, then sent immediately
+    /// to the remote peer, which finds the target entity from its [`EntityId`]
+    /// component. Once the remote peer acknowledges the edit, an
+    /// [`EditResult`] event is sent. If the remote peer failed to apply the
+    /// edit, it's first undone locally. Edits are rolled back independently of
+    /// each other, so an edit should not depend on an earlier one still
+    /// pending. Edits still pending when the connection closes are kept as is.
+    ///
+    /// Returns the sequence number of the edit message. The message type must
+    /// be registered in the [`MessageRegistry`] of the connection, otherwise
+    /// this returns an [`Error::UnknownMessageKind`]. Nothing is sent if
+    /// applying the edit locally fails.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the world has no [`RomeConnection`] resource.
+    pub fn send_edit<M: Serialize + 'static>(
+        world: &mut World,
+        message: &M,
+        target: Entity,
+    ) -> Result<u64, Error> {
+        world.resource_scope(|world, mut connection: Mut<RomeConnection>| {
+            let connection = connection.as_mut();
+            let send_queue = connection.send_queue.get_mut().unwrap();
+            let (seq, edit) = PendingEdit::apply_and_send(
+                world,
+                &connection.registry,
+                connection.codec,
+                message,
+                target,
+                |kind, id, payload| send_queue.send_payload(kind, payload, id),
+            )?;
+            connection.pending.insert(seq, edit);
+            Ok(seq)
+        })
+    }
+
+    /// Serialize a message into an [`OutgoingMessage`] event.
+    fn encode<M: Serialize + 'static>(
+        &self,
+        message: &M,
+        target: EntityId,
+    ) -> Result<OutgoingMessage, Error> {
+        let kind = self
+            .registry
+            .id::<M>()
+            .ok_or_else(|| Error::UnknownMessageKind(std::any::type_name::<M>().to_string()))?;
+        Ok(OutgoingMessage {
+            kind: kind.to_string(),
+            payload: self.codec.encode(message)?,
+            target,
+        })
+    }
+}
+
+/// System parameter to send messages to the remote peer of the
+/// [`RomeConnection`].
+///
+/// Messages are serialized immediately, but only sent at the end of the
+/// update, in the [`RomeSet::Send`] system set. The remote peer finds the
+/// target entity of a message from its [`EntityId`].
+#[derive(SystemParam)]
+pub struct RomeSender<'w, 's> {
+    connection: Res<'w, RomeConnection>,
+    events: EventWriter<'w, OutgoingMessage>,
+    ids: Query<'w, 's, &'static EntityId>,
+}
+
+impl RomeSender<'_, '_> {
+    /// Queue a message targetting the given entity.
+    ///
+    /// The message type must be registered in the [`MessageRegistry`] of the
+    /// connection, otherwise this returns an [`Error::UnknownMessageKind`]. The
+    /// entity must have an [`EntityId`] component, otherwise this returns an
+    /// [`Error::ComponentNotFound`].
+    pub fn send<M: Serialize + 'static>(
+        &mut self,
+        message: &M,
+        target: Entity,
+    ) -> Result<(), Error> {
+        let id = self.ids.get(target).map_err(|err| match err {
+            QueryEntityError::NoSuchEntity(entity) => Error::EntityNotFound(entity),
+            _ => Error::ComponentNotFound {
+                entity: target,
+                component: EntityId::type_path().to_string(),
+            },
+        })?;
+        self.send_to(message, *id)
+    }
+
+    /// Queue a message targetting the entity with the given identifier.
+    ///
+    /// Unlike [`send()`], the entity doesn't need to exist locally.
+    ///
+    /// [`send()`]: RomeSender::send
+    pub fn send_to<M: Serialize + 'static>(
+        &mut self,
+        message: &M,
+        target: EntityId,
+    ) -> Result<(), Error> {
+        let event = self.connection.encode(message, target)?;
+        self.events.send(event);
+        Ok(())
+    }
+}
+
+/// Plugin exchanging messages with a remote peer.
+///
+/// The plugin opens a [`RomeConnection`] over the given [`Transport`] once all
+/// plugins are built, so that the [`Handshake`] describes all the types
+/// registered in the [`AppTypeRegistry`]. Messages received from the remote
+/// peer are applied to their target entity in the [`RomeSet::Receive`] system
+/// set, and messages queued by systems with the [`RomeSender`] system parameter
+/// are sent in the [`RomeSet::Send`] one. Entities are identified across peers
+/// by their [`EntityId`]; the plugin adds the [`EntityMapPlugin`] if needed, to
+/// find the entity targetted by a received message.
+///
+/// # Panics
+///
+/// Finishing the plugin panics if the connection can't be opened, or if the
+/// plugin is finished more than once.
+pub struct RomePlugin {
+    transport: Mutex<Option<Box<dyn Transport>>>,
+    registry: Arc<MessageRegistry>,
+    codec: Codec,
+}
+
+impl RomePlugin {
+    /// Create a plugin exchanging the messages registered in `registry` over
+    /// the given transport.
+    pub fn new(transport: impl Transport, registry: MessageRegistry) -> Self {
+        Self {
+            transport: Mutex::new(Some(Box::new(transport))),
+            registry: Arc::new(registry),
+            codec: Codec::Ron,
+        }
+    }
+
+    /// Set the serialization format of the messages exchanged. Both peers must
+    /// use the same format.
+    pub fn with_codec(mut self, codec: Codec) -> Self {
+        self.codec = codec;
+        self
+    }
+}
+
+impl Plugin for RomePlugin {
+    fn build(&self, app: &mut App) {
+        if !app.is_plugin_added::<EntityMapPlugin>() {
+            app.add_plugins(EntityMapPlugin);
+        }
+        app.add_event::<OutgoingMessage>()
+            .add_event::<EditResult>()
+            .add_systems(PreUpdate, apply_received_messages.in_set(RomeSet::Receive))
+            .add_systems(PostUpdate, send_messages.in_set(RomeSet::Send));
+    }
+
+    fn finish(&self, app: &mut App) {
+        let transport = self
+            .transport
+            .lock()
+            .unwrap()
+            .take()
+            .expect("RomePlugin can only be finished once.");
+        let handshake = Handshake::new(
+            &self.registry,
+            &app.world.resource::<AppTypeRegistry>().read(),
+        );
+        let connection =
+            RomeConnection::open(transport, self.registry.clone(), self.codec, handshake)
+                .unwrap_or_else(|err| panic!("Failed to open connection: {err}"));
+        app.insert_resource(connection);
+    }
+}
+
+/// Apply all messages received since the last call to their target entity.
+///
+/// Each message is acknowledged to the remote peer with the result of applying
+/// it. Acknowledgements of edits sent with [`RomeConnection::send_edit()`] are
+/// processed too, rolling back the failed edits and sending an [`EditResult`]
+/// event for each of them.
+pub fn apply_received_messages(world: &mut World) {
+    world.resource_scope(|world, mut connection: Mut<RomeConnection>| {
+        let connection = connection.as_mut();
+        loop {
+            let result = match connection.incoming.get_mut().unwrap().try_recv() {
+                Ok(result) => result,
+                Err(mpsc::TryRecvError::Empty) => break,
+                // The background thread stops once the stream ended.
+                Err(mpsc::TryRecvError::Disconnected) => {
+                    connection.connected = false;
+                    break;
+                }
+            };
+            match result {
+                Ok(Received::Message(received)) => {
+                    let seq = received.seq;
+                    let result =
+                        apply_message(world, received).map_err(|err| RemoteError::from(&err));
+                    let send_queue = connection.send_queue.get_mut().unwrap();
+                    if let Err(err) = send_queue.send_ack(seq, result) {
+                        warn!("Failed to acknowledge message #{}: {}", seq, err);
+                    }
+                }
+                Ok(Received::Ack(ack)) => apply_ack(world, connection, ack),
+                Err(Error::Disconnected) => connection.connected = false,
+                Err(err) => {
+                    warn!("Connection closed after error: {}", err);
+                    connection.connected = false;
+                }
+            }
+        }
+    });
+}
+
+/// Apply a received message to its target entity.
+fn apply_message(world: &mut World, mut received: ReceivedMessage) -> Result<(), Error> {
+    let result = world
+        .resource::<EntityMap>()
+        .resolve(received.target)
+        .and_then(|entity| received.message.redo(world, entity));
+    if let Err(err) = &result {
+        warn!(
+            "Failed to apply message #{} to entity {}: {}",
+            received.seq, received.target, err
+        );
+    }
+    result
+}
+
+/// Complete the pending edit acknowledged by the remote peer, if any, rolling
+/// it back on error.
+fn apply_ack(world: &mut World, connection: &mut RomeConnection, ack: Ack) {
+    // Messages sent with RomeSender are not tracked
+    let Some(mut edit) = connection.pending.remove(&ack.seq) else {
+        return;
+    };
+    if let Err(err) = &ack.result {
+        warn!("Remote peer failed to apply edit #{}: {}", ack.seq, err);
+        if let Err(err) = edit.message.undo(world, edit.target) {
+            warn!(
+                "Failed to roll back edit #{} of entity {:?}: {}",
+                ack.seq, edit.target, err
+            );
+        }
+    }
+    world.send_event(EditResult {
+        seq: ack.seq,
+        target: edit.target,
+        result: ack.result,
+    });
+}
+
+/// Send all messages queued with [`RomeSender`].
+pub fn send_messages(
+    mut connection: ResMut<RomeConnection>,
+    mut events: EventReader<OutgoingMessage>,
+) {
+    let send_queue = connection.send_queue.get_mut().unwrap();
+    for message in events.read() {
+        if let Err(err) =
+            send_queue.send_payload(&message.kind, message.payload.clone(), message.target)
+        {
+            warn!(
+                "Failed to send message {} to entity {}: {}",
+                message.kind, message.target, err
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::queue::RecvQueue;
+    use crate::tests::{wait_until, PosMsg};
+    use crate::transport::{ChannelTransport, FrameReceiver, TransportHalves};
+    use bevy::app::Update;
+    use bevy::ecs::event::Events;
+    use bevy::math::Vec3;
+    use bevy::reflect::TypeRegistry;
+    use bevy::transform::components::Transform;
+
+    fn messages() -> MessageRegistry {
+        let mut messages = MessageRegistry::new();
+        messages.register::<PosMsg, Transform>();
+        messages
+    }
+
+    fn app(transport: ChannelTransport) -> App {
+        let mut app = App::new();
+        app.add_plugins(RomePlugin::new(transport, messages()));
+        app.finish();
+        app
+    }
+
+    /// Split the remote transport, and exchange handshakes with the app.
+    fn remote(transport: ChannelTransport, messages: &MessageRegistry) -> TransportHalves {
+        let (mut sender, mut receiver) = Box::new(transport).split().unwrap();
+        let handshake = Handshake::new(messages, &TypeRegistry::new());
+        handshake.send(&mut sender, Codec::Ron).unwrap();
+        Handshake::recv(&mut receiver, Codec::Ron).unwrap();
+        (sender, receiver)
+    }
+
+    /// Update the app until the condition holds, or panic after a while.
+    fn update_until(app: &mut App, condition: impl Fn(&mut World) -> bool) {
+        wait_until(|| {
+            app.update();
+            condition(&mut app.world)
+        });
+    }
+
+    #[test]
+    fn receive() {
+        let (local, remote) = ChannelTransport::pair();
+        let mut app = app(local);
+        let id = EntityId::new();
+        let entity = app.world.spawn((Transform::default(), id)).id();
+
+        let (sender, _receiver) = self::remote(remote, &messages());
+        let mut queue = SendQueue::new(sender, Arc::new(messages()));
+        queue.send(&PosMsg { pos: Vec3::ONE }, id).unwrap();
+        update_until(&mut app, |world| {
+            world.get::<Transform>(entity).unwrap().translation == Vec3::ONE
+        });
+        assert!(app.world.resource::<RomeConnection>().is_connected());
+
+        // Messages to unknown entities are ignored
+        queue
+            .send(&PosMsg { pos: Vec3::X }, EntityId::new())
+            .unwrap();
+        queue.send(&PosMsg { pos: Vec3::Y }, id).unwrap();
+        update_until(&mut app, |world| {
+            world.get::<Transform>(entity).unwrap().translation == Vec3::Y
+        });
+        assert!(app.world.resource::<RomeConnection>().is_connected());
+
+        // Unregistered messages close the connection
+        queue.send_payload("unknown", vec![], id).unwrap();
+        update_until(&mut app, |world| {
+            !world.resource::<RomeConnection>().is_connected()
+        });
+    }
+
+    #[test]
+    fn disconnect() {
+        let (local, remote) = ChannelTransport::pair();
+        let mut app = app(local);
+        app.update();
+        assert!(app.world.resource::<RomeConnection>().is_connected());
+        drop(remote);
+        update_until(&mut app, |world| {
+            !world.resource::<RomeConnection>().is_connected()
+        });
+    }
+
+    #[test]
+    fn handshake_mismatch() {
+        let (local, remote) = ChannelTransport::pair();
+        let mut app = app(local);
+        let id = EntityId::new();
+        let entity = app.world.spawn((Transform::default(), id)).id();
+
+        // The remote peer doesn't know about PosMsg
+        let (sender, mut receiver) = self::remote(remote, &MessageRegistry::new());
+        let mut queue = SendQueue::new(sender, Arc::new(messages()));
+        queue.send(&PosMsg { pos: Vec3::ONE }, id).unwrap();
+        update_until(&mut app, |world| {
+            !world.resource::<RomeConnection>().is_connected()
+        });
+        assert_eq!(
+            app.world.get::<Transform>(entity).unwrap().translation,
+            Vec3::ZERO
+        );
+
+        // The app closed its side of the connection
+        drop(app);
+        assert_eq!(receiver.recv_frame().unwrap(), None);
+    }
+
+    #[test]
+    fn send() {
+        let (local, remote) = ChannelTransport::pair();
+        let mut app = app(local);
+        let id = EntityId::new();
+        let entity = app.world.spawn(id).id();
+        let other = app.world.spawn_empty().id();
+        app.add_systems(Update, move |mut sender: RomeSender| {
+            let pos = PosMsg { pos: Vec3::ONE };
+            sender.send(&pos, entity).unwrap();
+            sender.send_to(&pos, id).unwrap();
+            assert!(matches!(
+                sender.send(&42_u32, entity),
+                Err(Error::UnknownMessageKind(kind)) if kind == "u32"
+            ));
+            assert!(matches!(
+                sender.send(&pos, other),
+                Err(Error::ComponentNotFound { entity, .. }) if entity == other
+            ));
+        });
+        app.update();
+        app.update();
+
+        let (_sender, receiver) = self::remote(remote, &messages());
+        let mut queue = RecvQueue::new(receiver, Arc::new(messages()));
+        for seq in 0..4 {
+            let Some(Received::Message(msg)) = queue.recv().unwrap() else {
+                panic!("Expected message.");
+            };
+            assert_eq!(msg.seq, seq);
+            assert_eq!(msg.target, id);
+        }
+    }
+
+    #[test]
+    fn edit() {
+        let (local, remote) = ChannelTransport::pair();
+        let mut editor = app(local);
+        let mut game = app(remote);
+        let id = EntityId::new();
+        let entity = editor.world.spawn((Transform::default(), id)).id();
+        let game_entity = game.world.spawn((Transform::default(), id)).id();
+        // Entity unknown to the game
+        let other_id = EntityId::new();
+        let other = editor.world.spawn((Transform::default(), other_id)).id();
+
+        let pos = PosMsg { pos: Vec3::ONE };
+        let seq = RomeConnection::send_edit(&mut editor.world, &pos, entity).unwrap();
+        let failed = RomeConnection::send_edit(&mut editor.world, &pos, other).unwrap();
+        assert!(matches!(
+            RomeConnection::send_edit(&mut editor.world, &42_u32, entity),
+            Err(Error::UnknownMessageKind(_))
+        ));
+        let translation =
+            |app: &App, entity| app.world.get::<Transform>(entity).unwrap().translation;
+        // Edits are applied locally right away
+        assert_eq!(translation(&editor, other), Vec3::ONE);
+        assert_eq!(editor.world.resource::<RomeConnection>().pending_edits(), 2);
+
+        let mut results = vec![];
+        wait_until(|| {
+            game.update();
+            editor.update();
+            results.extend(editor.world.resource_mut::<Events<EditResult>>().drain());
+            results.len() == 2
+        });
+        assert_eq!(
+            results,
+            [
+                EditResult {
+                    seq,
+                    target: entity,
+                    result: Ok(())
+                },
+                EditResult {
+                    seq: failed,
+                    target: other,
+                    result: Err(RemoteError::UnmappedEntity(other_id))
+                }
+            ]
+        );
+        assert_eq!(editor.world.resource::<RomeConnection>().pending_edits(), 0);
+        assert_eq!(translation(&game, game_entity), Vec3::ONE);
+        assert_eq!(translation(&editor, entity), Vec3::ONE);
+        // The failed edit was rolled back
+        assert_eq!(translation(&editor, other), Vec3::ZERO);
+    }
+}
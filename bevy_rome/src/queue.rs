@@ -0,0 +1,411 @@
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use super::codec::Codec;
+use super::entity::EntityId;
+use super::error::{Error, RemoteError};
+use super::registry::MessageRegistry;
+use super::transport::{FrameReceiver, FrameSender};
+use super::AnyMessage;
+
+/// Envelope wrapping a serialized message for transport.
+///
+/// The envelope records the kind of the message, its identifier in the
+/// [`MessageRegistry`] which allows the receiver to select the deserializer
+/// for the payload, the stable identifier of the entity targetted by the
+/// message, and a sequence number assigned by the sender.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Envelope {
+    kind: String,
+    target: EntityId,
+    seq: u64,
+    payload: Vec<u8>,
+}
+
+impl Envelope {
+    /// Create a new envelope for a serialized message.
+    pub fn new(kind: impl Into<String>, target: EntityId, seq: u64, payload: Vec<u8>) -> Self {
+        Self {
+            kind: kind.into(),
+            target,
+            seq,
+            payload,
+        }
+    }
+
+    /// Kind of the message.
+    pub fn kind(&self) -> &str {
+        &self.kind
+    }
+
+    /// Identifier of the entity targetted by the message.
+    pub fn target(&self) -> EntityId {
+        self.target
+    }
+
+    /// Sequence number of the message.
+    pub fn seq(&self) -> u64 {
+        self.seq
+    }
+
+    /// Serialized message.
+    pub fn payload(&self) -> &[u8] {
+        &self.payload
+    }
+}
+
+/// Acknowledgement of a message by its receiver, once applied.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Ack {
+    /// Sequence number of the acknowledged message.
+    pub seq: u64,
+    /// Result of applying the message.
+    pub result: Result<(), RemoteError>,
+}
+
+/// Single frame exchanged by a [`SendQueue`] and a [`RecvQueue`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+enum Frame {
+    Message(Envelope),
+    Ack(Ack),
+}
+
+/// Queue sending messages wrapped into [`Envelope`]s.
+///
+/// Each envelope, or [`Ack`] of a message received from the remote peer, is
+/// sent as a single frame through a [`FrameSender`], like the
+/// sending half of a [`Transport`] or a [`FrameWriter`]. Only message types
+/// registered in the [`MessageRegistry`] of the queue can be sent. Each
+/// message sent is assigned a sequence number, starting from zero and
+/// incremented with each message. Envelopes and messages are serialized with
+/// the [`Codec`] of the queue, by default [`Codec::Ron`].
+///
+/// [`Transport`]: crate::transport::Transport
+/// [`FrameWriter`]: crate::framing::FrameWriter
+pub struct SendQueue<S: FrameSender> {
+    sender: S,
+    registry: Arc<MessageRegistry>,
+    codec: Codec,
+    next_seq: u64,
+}
+
+impl<S: FrameSender> SendQueue<S> {
+    /// Create a new queue sending frames to the given sender.
+    pub fn new(sender: S, registry: Arc<MessageRegistry>) -> Self {
+        Self {
+            sender,
+            registry,
+            codec: Codec::Ron,
+            next_seq: 0,
+        }
+    }
+
+    /// Set the serialization format of the envelopes and messages sent.
+    pub fn with_codec(mut self, codec: Codec) -> Self {
+        self.codec = codec;
+        self
+    }
+
+    /// Send a message targetting the given entity, and return its sequence
+    /// number.
+    ///
+    /// The message type must be registered in the [`MessageRegistry`] of the
+    /// queue, otherwise this returns an [`Error::UnknownMessageKind`].
+    pub fn send<M: Serialize + 'static>(
+        &mut self,
+        message: &M,
+        target: EntityId,
+    ) -> Result<u64, Error> {
+        let registry = self.registry.clone();
+        let kind = registry
+            .id::<M>()
+            .ok_or_else(|| Error::UnknownMessageKind(std::any::type_name::<M>().to_string()))?;
+        let payload = self.codec.encode(message)?;
+        self.send_payload(kind, payload, target)
+    }
+
+    /// Send an already serialized message of the given kind targetting the
+    /// given entity, and return its sequence number.
+    ///
+    /// The payload must be serialized with the [`Codec`] of the queue. The kind
+    /// is not checked against the [`MessageRegistry`] of the queue.
+    pub fn send_payload(
+        &mut self,
+        kind: &str,
+        payload: Vec<u8>,
+        target: EntityId,
+    ) -> Result<u64, Error> {
+        let seq = self.next_seq;
+        let frame = self
+            .codec
+            .encode(&Frame::Message(Envelope::new(kind, target, seq, payload)))?;
+        self.sender.send_frame(&frame)?;
+        self.next_seq += 1;
+        Ok(seq)
+    }
+
+    /// Acknowledge a message received from the remote peer, with the result of
+    /// applying it.
+    pub fn send_ack(&mut self, seq: u64, result: Result<(), RemoteError>) -> Result<(), Error> {
+        let frame = self.codec.encode(&Frame::Ack(Ack { seq, result }))?;
+        self.sender.send_frame(&frame)
+    }
+
+    /// Registry of the messages the queue can send.
+    pub fn registry(&self) -> &Arc<MessageRegistry> {
+        &self.registry
+    }
+}
+
+/// Message received and decoded by a [`RecvQueue`].
+pub struct ReceivedMessage {
+    /// Sequence number assigned by the sender.
+    pub seq: u64,
+    /// Identifier of the entity targetted by the message.
+    pub target: EntityId,
+    /// Decoded message.
+    pub message: Box<dyn AnyMessage>,
+}
+
+/// Message or acknowledgement received by a [`RecvQueue`].
+pub enum Received {
+    /// Message sent with [`SendQueue::send()`].
+    Message(ReceivedMessage),
+    /// Acknowledgement sent with [`SendQueue::send_ack()`].
+    Ack(Ack),
+}
+
+/// Queue receiving messages wrapped into [`Envelope`]s.
+///
+/// Each envelope is received as a single frame from a [`FrameReceiver`], like
+/// the receiving half of a [`Transport`] or a [`FrameReader`]. The kind of
+/// each message is decoded first from its envelope, and used to dispatch the
+/// payload to the deserializer registered for that kind in the
+/// [`MessageRegistry`] of the queue. This allows sending messages of different
+/// types over the same stream. Messages whose kind is not registered produce
+/// an [`Error::UnknownMessageKind`]. The [`Codec`] of the queue must match the
+/// one of the sender.
+///
+/// [`Transport`]: crate::transport::Transport
+/// [`FrameReader`]: crate::framing::FrameReader
+pub struct RecvQueue<R: FrameReceiver> {
+    receiver: R,
+    registry: Arc<MessageRegistry>,
+    codec: Codec,
+}
+
+impl<R: FrameReceiver> RecvQueue<R> {
+    /// Create a new queue receiving frames from the given receiver, and
+    /// decoding the messages registered in `registry`.
+    pub fn new(receiver: R, registry: Arc<MessageRegistry>) -> Self {
+        Self {
+            receiver,
+            registry,
+            codec: Codec::Ron,
+        }
+    }
+
+    /// Set the serialization format of the envelopes and messages received.
+    pub fn with_codec(mut self, codec: Codec) -> Self {
+        self.codec = codec;
+        self
+    }
+
+    /// Receive the next message or acknowledgement, blocking until it's
+    /// available.
+    ///
+    /// Returns `None` once the underlying stream ended.
+    pub fn recv(&mut self) -> Result<Option<Received>, Error> {
+        let Some(frame) = self.receiver.recv_frame()? else {
+            return Ok(None);
+        };
+        decode_frame(&frame, &self.registry, self.codec).map(Some)
+    }
+
+    /// Decode the message contained in an envelope.
+    pub fn decode(&self, envelope: &Envelope) -> Result<ReceivedMessage, Error> {
+        decode_envelope(envelope, &self.registry, self.codec)
+    }
+}
+
+/// Decode a frame sent by a [`SendQueue`].
+pub(crate) fn decode_frame(
+    frame: &[u8],
+    registry: &MessageRegistry,
+    codec: Codec,
+) -> Result<Received, Error> {
+    match codec.decode(frame)? {
+        Frame::Message(envelope) => {
+            decode_envelope(&envelope, registry, codec).map(Received::Message)
+        }
+        Frame::Ack(ack) => Ok(Received::Ack(ack)),
+    }
+}
+
+fn decode_envelope(
+    envelope: &Envelope,
+    registry: &MessageRegistry,
+    codec: Codec,
+) -> Result<ReceivedMessage, Error> {
+    Ok(ReceivedMessage {
+        seq: envelope.seq(),
+        target: envelope.target(),
+        message: registry.deserialize(envelope.kind(), envelope.payload(), codec)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entity::EntityMap;
+    use crate::framing::{FrameReader, FrameWriter};
+    use crate::tests::PosMsg;
+    use crate::transport::{ChannelTransport, TcpTransport, Transport};
+    use crate::SetFieldMsg;
+    use bevy::ecs::{reflect::AppTypeRegistry, world::World};
+    use bevy::math::Vec3;
+    use bevy::transform::components::Transform;
+    use std::net::TcpListener;
+
+    fn messages() -> MessageRegistry {
+        let mut messages = MessageRegistry::new();
+        messages.register::<PosMsg, Transform>();
+        messages.register_any::<SetFieldMsg>();
+        messages
+    }
+
+    #[test]
+    fn envelope() {
+        let mut world = crate::tests::world();
+        let id = EntityId::new();
+        let entity = world.spawn((Transform::default(), id)).id();
+        let map = EntityMap::from_world(&mut world);
+
+        let mut bytes = vec![];
+        let mut queue = SendQueue::new(FrameWriter::new(&mut bytes), Arc::new(messages()));
+        assert!(matches!(
+            queue.send(&42_u32, id),
+            Err(Error::UnknownMessageKind(kind)) if kind == "u32"
+        ));
+        let msg = PosMsg {
+            pos: Vec3::splat(1.),
+        };
+        assert_eq!(queue.send(&msg, id).unwrap(), 0);
+        let registry = world.resource::<AppTypeRegistry>().clone();
+        let msg =
+            SetFieldMsg::of::<Transform>("", &Transform::IDENTITY, &registry.read(), Codec::Ron)
+                .unwrap();
+        assert_eq!(queue.send(&msg, id).unwrap(), 1);
+        queue
+            .send_ack(7, Err(RemoteError::UnmappedEntity(id)))
+            .unwrap();
+
+        // Unregistered kinds are rejected
+        let mut partial = MessageRegistry::new();
+        partial.register::<PosMsg, Transform>();
+        let mut queue = RecvQueue::new(FrameReader::new(bytes.as_slice()), Arc::new(partial));
+        assert!(queue.recv().unwrap().is_some());
+        assert!(matches!(
+            queue.recv(),
+            Err(Error::UnknownMessageKind(kind)) if kind == "bevy_rome::SetFieldMsg"
+        ));
+
+        let mut queue = RecvQueue::new(FrameReader::new(bytes.as_slice()), Arc::new(messages()));
+        for seq in 0..2 {
+            let Some(Received::Message(mut msg)) = queue.recv().unwrap() else {
+                panic!("Expected message.");
+            };
+            assert_eq!(msg.seq, seq);
+            assert_eq!(msg.target, id);
+            let target = map.resolve(msg.target).unwrap();
+            msg.message.redo(&mut world, target).unwrap();
+        }
+        let Some(Received::Ack(ack)) = queue.recv().unwrap() else {
+            panic!("Expected acknowledgement.");
+        };
+        assert_eq!(ack.seq, 7);
+        assert_eq!(ack.result, Err(RemoteError::UnmappedEntity(id)));
+        assert!(queue.recv().unwrap().is_none());
+        assert_eq!(
+            *world.get::<Transform>(entity).unwrap(),
+            Transform::IDENTITY
+        );
+    }
+
+    #[test]
+    fn max_frame_size() {
+        let mut bytes = vec![];
+        let writer = FrameWriter::new(&mut bytes).with_max_frame_size(16);
+        let mut queue = SendQueue::new(writer, Arc::new(messages()));
+        let msg = PosMsg { pos: Vec3::ONE };
+        assert!(matches!(
+            queue.send(&msg, EntityId::new()),
+            Err(Error::FrameTooLarge { max: 16, .. })
+        ));
+        assert!(bytes.is_empty());
+
+        let mut queue = SendQueue::new(FrameWriter::new(&mut bytes), Arc::new(messages()));
+        queue.send(&msg, EntityId::new()).unwrap();
+        let reader = FrameReader::new(bytes.as_slice()).with_max_frame_size(16);
+        let mut queue = RecvQueue::new(reader, Arc::new(messages()));
+        assert!(matches!(
+            queue.recv(),
+            Err(Error::FrameTooLarge { max: 16, .. })
+        ));
+    }
+
+    /// Send messages from one transport to the other, and apply them.
+    fn transport(a: impl Transport, b: impl Transport, codec: Codec) -> Result<(), Error> {
+        let target = EntityId::new();
+        let sender = std::thread::spawn(move || -> Result<(), Error> {
+            let (sender, _) = Box::new(a).split()?;
+            let mut queue = SendQueue::new(sender, Arc::new(messages())).with_codec(codec);
+            for i in 0..100 {
+                let msg = PosMsg {
+                    pos: Vec3::splat(i as f32),
+                };
+                queue.send(&msg, target)?;
+            }
+            Ok(())
+        });
+
+        let (_, receiver) = Box::new(b).split()?;
+        let mut queue = RecvQueue::new(receiver, Arc::new(messages())).with_codec(codec);
+        let mut world = World::new();
+        let entity = world.spawn(Transform::default()).id();
+        let mut seq = 0;
+        while let Some(Received::Message(mut msg)) = queue.recv()? {
+            assert_eq!(msg.seq, seq);
+            assert_eq!(msg.target, target);
+            msg.message.redo(&mut world, entity)?;
+            seq += 1;
+        }
+        sender.join().unwrap()?;
+        assert_eq!(seq, 100);
+        assert_eq!(
+            world.get::<Transform>(entity).unwrap().translation,
+            Vec3::splat(99.)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn channel() -> Result<(), Error> {
+        for codec in [Codec::Ron, Codec::Binary] {
+            let (a, b) = ChannelTransport::pair();
+            transport(a, b, codec)?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn tcp() -> Result<(), Error> {
+        for codec in [Codec::Ron, Codec::Binary] {
+            let listener = TcpListener::bind("127.0.0.1:0")?;
+            let client = TcpTransport::connect(listener.local_addr()?)?;
+            let server = TcpTransport::new(listener.accept()?.0)?;
+            transport(client, server, codec)?;
+        }
+        Ok(())
+    }
+}
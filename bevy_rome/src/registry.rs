@@ -0,0 +1,187 @@
+use bevy::ecs::component::Component;
+use bevy::reflect::TypePath;
+use bevy::utils::HashMap;
+use serde::de::DeserializeOwned;
+use std::any::TypeId;
+
+use super::codec::Codec;
+use super::error::Error;
+use super::{AnyMessage, ComponentMessage, Message};
+
+type DeserializeFn = fn(&[u8], Codec) -> Result<Box<dyn AnyMessage>, Error>;
+
+/// Registry of the message types which can be sent and received.
+///
+/// Each message type is registered with a stable string identifier, by default
+/// its type path. Unlike its [`TypeId`], this identifier doesn't depend on the
+/// compiler and build, so can be used to identify the message type across
+/// processes. The sender and the receiver of messages must register the same
+/// message types with the same identifiers.
+#[derive(Default, Clone)]
+pub struct MessageRegistry {
+    ids: HashMap<TypeId, String>,
+    deserializers: HashMap<String, DeserializeFn>,
+}
+
+impl MessageRegistry {
+    /// Create a new empty registry.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a [`Message`] modifying the component `T`, identified by its
+    /// type path.
+    ///
+    /// Received messages of this type are decoded as a [`ComponentMessage`].
+    pub fn register<M, T>(&mut self)
+    where
+        M: for<'de> Message<'de, T> + TypePath + Send + Sync + 'static,
+        T: Component + TypePath,
+    {
+        self.register_with_id::<M, T>(M::type_path());
+    }
+
+    /// Register a [`Message`] modifying the component `T`, with a custom
+    /// identifier.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the identifier is already used by another message type.
+    pub fn register_with_id<M, T>(&mut self, id: impl Into<String>)
+    where
+        M: for<'de> Message<'de, T> + Send + Sync + 'static,
+        T: Component + TypePath,
+    {
+        self.insert::<M>(id.into(), deserialize_component::<M, T>);
+    }
+
+    /// Register a type-erased [`AnyMessage`], like [`SetFieldMsg`], identified
+    /// by its type path.
+    ///
+    /// [`SetFieldMsg`]: crate::SetFieldMsg
+    pub fn register_any<M: AnyMessage + DeserializeOwned + TypePath>(&mut self) {
+        self.register_any_with_id::<M>(M::type_path());
+    }
+
+    /// Register a type-erased [`AnyMessage`] with a custom identifier.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the identifier is already used by another message type.
+    pub fn register_any_with_id<M: AnyMessage + DeserializeOwned>(
+        &mut self,
+        id: impl Into<String>,
+    ) {
+        self.insert::<M>(id.into(), deserialize_any::<M>);
+    }
+
+    fn insert<M: 'static>(&mut self, id: String, deserialize: DeserializeFn) {
+        let type_id = TypeId::of::<M>();
+        assert!(
+            self.ids
+                .iter()
+                .all(|(other, other_id)| *other == type_id || *other_id != id),
+            "Message id '{id}' is already registered for another type."
+        );
+        if let Some(old_id) = self.ids.insert(type_id, id.clone()) {
+            self.deserializers.remove(&old_id);
+        }
+        self.deserializers.insert(id, deserialize);
+    }
+
+    /// Get the identifier of a registered message type.
+    pub fn id<M: 'static>(&self) -> Option<&str> {
+        self.ids.get(&TypeId::of::<M>()).map(String::as_str)
+    }
+
+    /// Check if a message identifier is registered.
+    pub fn contains(&self, id: &str) -> bool {
+        self.deserializers.contains_key(id)
+    }
+
+    /// Iterate over the identifiers of all registered message types, in no
+    /// particular order.
+    pub fn ids(&self) -> impl Iterator<Item = &str> + '_ {
+        self.deserializers.keys().map(String::as_str)
+    }
+
+    /// Deserialize a message from its identifier and serialized payload.
+    pub fn deserialize(
+        &self,
+        id: &str,
+        payload: &[u8],
+        codec: Codec,
+    ) -> Result<Box<dyn AnyMessage>, Error> {
+        let deserialize = self
+            .deserializers
+            .get(id)
+            .ok_or_else(|| Error::UnknownMessageKind(id.to_string()))?;
+        deserialize(payload, codec)
+    }
+}
+
+fn deserialize_component<M, T>(payload: &[u8], codec: Codec) -> Result<Box<dyn AnyMessage>, Error>
+where
+    M: for<'de> Message<'de, T> + Send + Sync + 'static,
+    T: Component + TypePath,
+{
+    let message: M = codec.decode(payload)?;
+    Ok(Box::new(ComponentMessage::<M, T>::new(message)))
+}
+
+fn deserialize_any<M: AnyMessage + DeserializeOwned>(
+    payload: &[u8],
+    codec: Codec,
+) -> Result<Box<dyn AnyMessage>, Error> {
+    let message: M = codec.decode(payload)?;
+    Ok(Box::new(message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::PosMsg;
+    use crate::SetFieldMsg;
+    use bevy::math::Vec3;
+    use bevy::transform::components::Transform;
+
+    #[test]
+    fn register() {
+        let mut registry = MessageRegistry::new();
+        assert_eq!(registry.id::<PosMsg>(), None);
+        registry.register::<PosMsg, Transform>();
+        registry.register_any_with_id::<SetFieldMsg>("set_field");
+        assert_eq!(registry.id::<PosMsg>(), Some("bevy_rome::tests::PosMsg"));
+        assert_eq!(registry.id::<SetFieldMsg>(), Some("set_field"));
+        assert!(registry.contains("set_field"));
+        let mut ids: Vec<_> = registry.ids().collect();
+        ids.sort();
+        assert_eq!(ids, ["bevy_rome::tests::PosMsg", "set_field"]);
+
+        // Re-registering replaces the previous id
+        registry.register_with_id::<PosMsg, Transform>("pos");
+        assert_eq!(registry.id::<PosMsg>(), Some("pos"));
+        assert!(!registry.contains("bevy_rome::tests::PosMsg"));
+
+        for codec in [Codec::Ron, Codec::Binary] {
+            let payload = codec.encode(&PosMsg { pos: Vec3::ONE }).unwrap();
+            let msg = registry.deserialize("pos", &payload, codec).unwrap();
+            assert!(msg
+                .as_any()
+                .downcast_ref::<ComponentMessage<PosMsg, Transform>>()
+                .is_some());
+            assert!(matches!(
+                registry.deserialize("bevy_rome::tests::PosMsg", &payload, codec),
+                Err(Error::UnknownMessageKind(kind)) if kind == "bevy_rome::tests::PosMsg"
+            ));
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn register_duplicate_id() {
+        let mut registry = MessageRegistry::new();
+        registry.register_with_id::<PosMsg, Transform>("msg");
+        registry.register_any_with_id::<SetFieldMsg>("msg");
+    }
+}
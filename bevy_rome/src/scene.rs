@@ -0,0 +1,555 @@
+use bevy::ecs::{entity::Entity, reflect::AppTypeRegistry};
+use bevy::reflect::{
+    serde::{TypedReflectDeserializer, TypedReflectSerializer},
+    Reflect, ReflectFromReflect, TypePath, TypeRegistry,
+};
+use bevy::utils::Uuid;
+use ron::ser::PrettyConfig;
+use serde::{
+    de::{self, DeserializeSeed, MapAccess, Visitor},
+    ser::SerializeStruct,
+    Deserialize, Serialize, Serializer,
+};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+
+use super::codec::Codec;
+use super::entity::EntityId;
+use super::error::Error;
+use super::snapshot::Snapshot;
+use super::truth::{Object, ObjectMsg, Truth};
+
+/// On-disk storage of the objects of a [`Truth`], designed for version
+/// control.
+///
+/// A scene is saved as RON text with a deterministic layout, so that saving
+/// the same objects always produces the same text, and editing an object only
+/// changes the lines of that object:
+/// - objects are written as a map sorted by [`EntityId`], one block per
+///   object, with the identifier as key instead of any entity index;
+/// - each block holds the type and parent of the object, and its other
+///   components sorted by type path;
+/// - nothing else is written, like the version of the truth, which would
+///   change with each save.
+///
+/// ```text
+/// {
+///     "00000000-0000-0000-0000-000000000001": (
+///         type: "Material",
+///         parent: None,
+///         components: {
+///             "my_game::Material": (
+///                 roughness: 0.5,
+///             ),
+///         },
+///     ),
+/// }
+/// ```
+///
+/// Fields of a map type are written in the iteration order of the map, which
+/// is not deterministic for hash maps.
+#[derive(Debug, Default)]
+pub struct Scene {
+    objects: BTreeMap<EntityId, SceneObject>,
+}
+
+#[derive(Debug)]
+struct SceneObject {
+    object: Object,
+    components: BTreeMap<String, Box<dyn Reflect>>,
+}
+
+impl Scene {
+    /// Create an empty scene.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Capture all existing objects of a truth.
+    ///
+    /// Only components whose type is registered with its [`ReflectComponent`]
+    /// type data are captured.
+    ///
+    /// [`ReflectComponent`]: bevy::ecs::reflect::ReflectComponent
+    pub fn capture(truth: &Truth) -> Self {
+        let registry = truth.world().resource::<AppTypeRegistry>().read();
+        let ids: BTreeMap<Entity, EntityId> = truth
+            .objects()
+            .filter_map(|(id, _)| Some((truth.entity(id)?, id)))
+            .collect();
+        let snapshot = Snapshot::capture_entities(truth.world(), ids.keys().copied(), &registry);
+        let mut scene = Self::new();
+        for (entity, id) in ids {
+            let components = snapshot
+                .components(entity)
+                .filter(|&type_path| {
+                    type_path != EntityId::type_path() && type_path != Object::type_path()
+                })
+                .map(|type_path| {
+                    let value = snapshot.get(entity, type_path).unwrap();
+                    (type_path.to_string(), value.clone_value())
+                })
+                .collect();
+            let object = truth.object(id).unwrap().clone();
+            scene.objects.insert(id, SceneObject { object, components });
+        }
+        scene
+    }
+
+    /// Insert an object with the given components, replacing any object with
+    /// the same identifier.
+    pub fn insert(&mut self, id: EntityId, object: Object, components: Vec<Box<dyn Reflect>>) {
+        let components = components
+            .into_iter()
+            .map(|value| (value.reflect_type_path().to_string(), value))
+            .collect();
+        self.objects.insert(id, SceneObject { object, components });
+    }
+
+    /// Iterate over the objects of the scene, in increasing identifier order.
+    pub fn objects(&self) -> impl Iterator<Item = (EntityId, &Object)> + '_ {
+        self.objects
+            .iter()
+            .map(|(id, object)| (*id, &object.object))
+    }
+
+    /// Get an object of the scene, if any.
+    pub fn object(&self, id: EntityId) -> Option<&Object> {
+        self.objects.get(&id).map(|object| &object.object)
+    }
+
+    /// Iterate over the type paths of the components of an object, other than
+    /// [`Object`], in increasing order.
+    pub fn components(&self, id: EntityId) -> impl Iterator<Item = &str> + '_ {
+        self.objects
+            .get(&id)
+            .into_iter()
+            .flat_map(|object| object.components.keys().map(String::as_str))
+    }
+
+    /// Get the value of a component of an object.
+    pub fn get(&self, id: EntityId, component: &str) -> Option<&dyn Reflect> {
+        self.objects
+            .get(&id)?
+            .components
+            .get(component)
+            .map(|value| value.as_ref())
+    }
+
+    /// Queue creating all the objects of the scene in a truth, as a single
+    /// transaction.
+    ///
+    /// The objects keep their identifier, which must not be used by an existing
+    /// object of the truth, otherwise this returns an
+    /// [`Error::DuplicateEntity`]. The parent of each object must be an object
+    /// of the scene or of the truth, otherwise this returns an
+    /// [`Error::InvalidParent`]. The objects are only created by the next call
+    /// to [`Truth::apply()`].
+    pub fn instantiate(&self, truth: &mut Truth) -> Result<(), Error> {
+        let codec = truth.codec();
+        let messages = {
+            let registry = truth.world().resource::<AppTypeRegistry>().read();
+            self.objects
+                .iter()
+                .map(|(&id, object)| {
+                    if truth.entity(id).is_some() {
+                        return Err(Error::DuplicateEntity(id));
+                    }
+                    if let Some(parent) = object.object.parent {
+                        if !self.objects.contains_key(&parent) && truth.entity(parent).is_none() {
+                            return Err(Error::InvalidParent { object: id, parent });
+                        }
+                    }
+                    let components: Vec<&dyn Reflect> = object
+                        .components
+                        .values()
+                        .map(|value| value.as_ref())
+                        .collect();
+                    ObjectMsg::create(id, &object.object, &components, &registry, codec)
+                })
+                .collect::<Result<Vec<_>, Error>>()?
+        };
+        let history = truth.history_mut();
+        history.begin_transaction();
+        for message in messages {
+            history.push_dyn(Entity::PLACEHOLDER, Box::new(message));
+        }
+        history.commit_transaction();
+        Ok(())
+    }
+
+    /// Serialize the scene to RON text.
+    pub fn to_ron(&self, registry: &TypeRegistry) -> Result<String, Error> {
+        // Always use Unix line endings, to produce the same text on all
+        // platforms.
+        let config = PrettyConfig::default().new_line("\n".to_string());
+        let serializer = SceneSerializer {
+            scene: self,
+            registry,
+        };
+        Ok(ron::ser::to_string_pretty(&serializer, config)? + "\n")
+    }
+
+    /// Deserialize a scene from RON text.
+    ///
+    /// The types of all components must be registered in `registry`.
+    pub fn from_ron(text: &str, registry: &TypeRegistry) -> Result<Self, Error> {
+        Codec::Ron.decode_seed(SceneDeserializer { registry }, text.as_bytes())
+    }
+
+    /// Save the scene to a RON file.
+    pub fn save(&self, path: impl AsRef<Path>, registry: &TypeRegistry) -> Result<(), Error> {
+        Ok(std::fs::write(path, self.to_ron(registry)?)?)
+    }
+
+    /// Load a scene from a RON file.
+    pub fn load(path: impl AsRef<Path>, registry: &TypeRegistry) -> Result<Self, Error> {
+        Self::from_ron(&std::fs::read_to_string(path)?, registry)
+    }
+}
+
+struct SceneSerializer<'a> {
+    scene: &'a Scene,
+    registry: &'a TypeRegistry,
+}
+
+impl Serialize for SceneSerializer<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(self.scene.objects.iter().map(|(id, object)| {
+            (
+                id.uuid(),
+                SceneObjectSerializer {
+                    object,
+                    registry: self.registry,
+                },
+            )
+        }))
+    }
+}
+
+struct SceneObjectSerializer<'a> {
+    object: &'a SceneObject,
+    registry: &'a TypeRegistry,
+}
+
+impl Serialize for SceneObjectSerializer<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("SceneObject", 3)?;
+        state.serialize_field("type", &self.object.object.object_type)?;
+        state.serialize_field("parent", &self.object.object.parent.map(|id| id.uuid()))?;
+        state.serialize_field(
+            "components",
+            &ComponentsSerializer {
+                components: &self.object.components,
+                registry: self.registry,
+            },
+        )?;
+        state.end()
+    }
+}
+
+struct ComponentsSerializer<'a> {
+    components: &'a BTreeMap<String, Box<dyn Reflect>>,
+    registry: &'a TypeRegistry,
+}
+
+impl Serialize for ComponentsSerializer<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(self.components.iter().map(|(type_path, value)| {
+            (
+                type_path,
+                TypedReflectSerializer::new(value.as_ref(), self.registry),
+            )
+        }))
+    }
+}
+
+struct SceneDeserializer<'a> {
+    registry: &'a TypeRegistry,
+}
+
+impl<'de> DeserializeSeed<'de> for SceneDeserializer<'_> {
+    type Value = Scene;
+
+    fn deserialize<D: de::Deserializer<'de>>(self, deserializer: D) -> Result<Scene, D::Error> {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de> Visitor<'de> for SceneDeserializer<'_> {
+    type Value = Scene;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a map of objects")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Scene, A::Error> {
+        let mut scene = Scene::new();
+        while let Some(uuid) = map.next_key::<Uuid>()? {
+            let object = map.next_value_seed(SceneObjectDeserializer {
+                registry: self.registry,
+            })?;
+            let id = EntityId::from_uuid(uuid);
+            if scene.objects.insert(id, object).is_some() {
+                return Err(de::Error::custom(format!("duplicate object {id}")));
+            }
+        }
+        Ok(scene)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(field_identifier, rename_all = "lowercase")]
+enum SceneObjectField {
+    Type,
+    Parent,
+    Components,
+}
+
+const SCENE_OBJECT_FIELDS: &[&str] = &["type", "parent", "components"];
+
+struct SceneObjectDeserializer<'a> {
+    registry: &'a TypeRegistry,
+}
+
+impl<'de> DeserializeSeed<'de> for SceneObjectDeserializer<'_> {
+    type Value = SceneObject;
+
+    fn deserialize<D: de::Deserializer<'de>>(
+        self,
+        deserializer: D,
+    ) -> Result<SceneObject, D::Error> {
+        deserializer.deserialize_struct("SceneObject", SCENE_OBJECT_FIELDS, self)
+    }
+}
+
+impl<'de> Visitor<'de> for SceneObjectDeserializer<'_> {
+    type Value = SceneObject;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("an object")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<SceneObject, A::Error> {
+        let mut object_type = None;
+        let mut parent = None;
+        let mut components = None;
+        while let Some(field) = map.next_key()? {
+            match field {
+                SceneObjectField::Type => {
+                    if object_type.is_some() {
+                        return Err(de::Error::duplicate_field("type"));
+                    }
+                    object_type = Some(map.next_value::<String>()?);
+                }
+                SceneObjectField::Parent => {
+                    if parent.is_some() {
+                        return Err(de::Error::duplicate_field("parent"));
+                    }
+                    parent = Some(map.next_value::<Option<Uuid>>()?);
+                }
+                SceneObjectField::Components => {
+                    if components.is_some() {
+                        return Err(de::Error::duplicate_field("components"));
+                    }
+                    components = Some(map.next_value_seed(ComponentsDeserializer {
+                        registry: self.registry,
+                    })?);
+                }
+            }
+        }
+        let object = Object {
+            object_type: object_type.ok_or_else(|| de::Error::missing_field("type"))?,
+            parent: parent.flatten().map(EntityId::from_uuid),
+        };
+        Ok(SceneObject {
+            object,
+            components: components.unwrap_or_default(),
+        })
+    }
+}
+
+struct ComponentsDeserializer<'a> {
+    registry: &'a TypeRegistry,
+}
+
+impl<'de> DeserializeSeed<'de> for ComponentsDeserializer<'_> {
+    type Value = BTreeMap<String, Box<dyn Reflect>>;
+
+    fn deserialize<D: de::Deserializer<'de>>(
+        self,
+        deserializer: D,
+    ) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de> Visitor<'de> for ComponentsDeserializer<'_> {
+    type Value = BTreeMap<String, Box<dyn Reflect>>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a map of components")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let mut components = BTreeMap::new();
+        while let Some(type_path) = map.next_key::<String>()? {
+            let registration = self
+                .registry
+                .get_with_type_path(&type_path)
+                .ok_or_else(|| de::Error::custom(Error::UnregisteredType(type_path.clone())))?;
+            let value =
+                map.next_value_seed(TypedReflectDeserializer::new(registration, self.registry))?;
+            // Convert the dynamic value into the concrete type if possible
+            let value = registration
+                .data::<ReflectFromReflect>()
+                .and_then(|from_reflect| from_reflect.from_reflect(value.as_ref()))
+                .unwrap_or(value);
+            if components.insert(type_path.clone(), value).is_some() {
+                return Err(de::Error::custom(format!(
+                    "duplicate component '{type_path}'"
+                )));
+            }
+        }
+        Ok(components)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::ecs::{component::Component, reflect::ReflectComponent};
+
+    #[derive(Debug, Default, Clone, PartialEq, Component, Reflect)]
+    #[reflect(Component)]
+    struct Material {
+        roughness: f32,
+        tint: Option<[f32; 3]>,
+    }
+
+    fn truth() -> Truth {
+        let registry = AppTypeRegistry::default();
+        {
+            let mut registry = registry.write();
+            registry.register::<Material>();
+            registry.register::<Option<[f32; 3]>>();
+            registry.register::<[f32; 3]>();
+        }
+        Truth::new(registry)
+    }
+
+    #[test]
+    fn format() {
+        let truth = truth();
+        let registry = truth.world().resource::<AppTypeRegistry>().read();
+        let root = EntityId::from_uuid(Uuid::from_u128(1));
+        let child = EntityId::from_uuid(Uuid::from_u128(2));
+        let mut scene = Scene::new();
+        let material = Material {
+            roughness: 0.5,
+            tint: None,
+        };
+        scene.insert(
+            child,
+            Object::new("Material").with_parent(root),
+            vec![Box::new(material.clone())],
+        );
+        scene.insert(root, Object::new("Folder"), vec![]);
+        let text = scene.to_ron(&registry).unwrap();
+        assert_eq!(
+            text,
+            r#"{
+    "00000000-0000-0000-0000-000000000001": (
+        type: "Folder",
+        parent: None,
+        components: {},
+    ),
+    "00000000-0000-0000-0000-000000000002": (
+        type: "Material",
+        parent: Some("00000000-0000-0000-0000-000000000001"),
+        components: {
+            "bevy_rome::scene::tests::Material": (
+                roughness: 0.5,
+                tint: None,
+            ),
+        },
+    ),
+}
+"#
+        );
+
+        let loaded = Scene::from_ron(&text, &registry).unwrap();
+        assert!(loaded.objects().map(|(id, _)| id).eq([root, child]));
+        assert_eq!(loaded.object(child).unwrap().parent, Some(root));
+        assert!(loaded.components(child).eq([Material::type_path()]));
+        let value = loaded.get(child, Material::type_path()).unwrap();
+        assert_eq!(value.downcast_ref::<Material>(), Some(&material));
+        assert_eq!(loaded.to_ron(&registry).unwrap(), text);
+
+        let text = text.replace("bevy_rome::scene::tests::Material", "Unknown");
+        assert!(matches!(
+            Scene::from_ron(&text, &registry),
+            Err(Error::Ron {
+                position: Some(_),
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn truth_round_trip() {
+        let mut truth = truth();
+        let root = truth.create(Object::new("Folder"), &[]).unwrap();
+        truth.apply().unwrap();
+        let material = Material {
+            roughness: 0.25,
+            tint: Some([1., 0., 0.]),
+        };
+        let child = truth
+            .create(Object::new("Material").with_parent(root), &[&material])
+            .unwrap();
+        truth.apply().unwrap();
+        let text = {
+            let registry = truth.world().resource::<AppTypeRegistry>().read();
+            Scene::capture(&truth).to_ron(&registry).unwrap()
+        };
+        assert!(!text.contains("EntityId"));
+
+        let path = std::env::temp_dir().join(format!("bevy_rome_scene_{}.ron", Uuid::new_v4()));
+        std::fs::write(&path, &text).unwrap();
+        let mut copy = self::truth();
+        let scene = {
+            let registry = copy.world().resource::<AppTypeRegistry>().read();
+            Scene::load(&path, &registry).unwrap()
+        };
+        std::fs::remove_file(&path).unwrap();
+        scene.instantiate(&mut copy).unwrap();
+        copy.apply().unwrap();
+        assert_eq!(copy.children(root), [child]);
+        assert_eq!(copy.get::<Material>(child), Some(&material));
+
+        // Saving again produces the same text, even if the objects were
+        // created in a different order
+        {
+            let registry = copy.world().resource::<AppTypeRegistry>().read();
+            assert_eq!(Scene::capture(&copy).to_ron(&registry).unwrap(), text);
+        }
+
+        // Loading is a single undoable operation
+        assert!(matches!(
+            scene.instantiate(&mut copy),
+            Err(Error::DuplicateEntity(_))
+        ));
+        copy.history_mut().undo();
+        copy.apply().unwrap();
+        assert_eq!(copy.objects().count(), 0);
+
+        let mut orphan = Scene::new();
+        orphan.insert(child, Object::new("Material").with_parent(root), vec![]);
+        assert!(matches!(
+            orphan.instantiate(&mut copy),
+            Err(Error::InvalidParent { object, parent }) if object == child && parent == root
+        ));
+    }
+}
@@ -0,0 +1,380 @@
+use bevy::ecs::{entity::Entity, reflect::ReflectComponent, world::World};
+use bevy::reflect::{Reflect, TypeRegistry};
+use std::collections::{BTreeMap, BTreeSet};
+
+use super::codec::Codec;
+use super::diff::{Diff, DiffContent, DiffData, DiffTarget};
+use super::error::Error;
+
+/// Snapshot of the reflected components of a set of entities of a [`World`].
+///
+/// Only components whose type is registered with its [`ReflectComponent`]
+/// type data are captured. Components are identified by their type path, and
+/// stored as a copy of their reflected value, such that the snapshot is
+/// independent of the world it was captured from.
+///
+/// Two snapshots of the same world taken at different times can be compared
+/// with [`diff()`] to produce the list of [`Diff`]s transforming one into the
+/// other.
+///
+/// [`diff()`]: Snapshot::diff
+#[derive(Debug, Default)]
+pub struct Snapshot {
+    entities: BTreeMap<Entity, BTreeMap<String, Box<dyn Reflect>>>,
+}
+
+impl Snapshot {
+    /// Capture a snapshot of all entities of a world.
+    pub fn capture(world: &World, registry: &TypeRegistry) -> Self {
+        Self::capture_entities(world, world.iter_entities().map(|e| e.id()), registry)
+    }
+
+    /// Capture a snapshot of a set of entities of a world.
+    ///
+    /// Entities which don't exist in the world are ignored.
+    pub fn capture_entities(
+        world: &World,
+        entities: impl IntoIterator<Item = Entity>,
+        registry: &TypeRegistry,
+    ) -> Self {
+        let mut snapshot = Self::default();
+        for entity in entities {
+            let Some(entity_ref) = world.get_entity(entity) else {
+                continue;
+            };
+            let components = entity_ref
+                .archetype()
+                .components()
+                .filter_map(|component_id| {
+                    let type_id = world.components().get_info(component_id)?.type_id()?;
+                    let registration = registry.get(type_id)?;
+                    let value = registration
+                        .data::<ReflectComponent>()?
+                        .reflect(entity_ref)?;
+                    Some((
+                        registration.type_info().type_path().to_string(),
+                        value.clone_value(),
+                    ))
+                })
+                .collect();
+            snapshot.entities.insert(entity, components);
+        }
+        snapshot
+    }
+
+    /// Check if the snapshot contains an entity.
+    pub fn contains(&self, entity: Entity) -> bool {
+        self.entities.contains_key(&entity)
+    }
+
+    /// Iterate over the entities of the snapshot, in increasing order.
+    pub fn entities(&self) -> impl Iterator<Item = Entity> + '_ {
+        self.entities.keys().copied()
+    }
+
+    /// Iterate over the type paths of the components of an entity, in
+    /// increasing order.
+    pub fn components(&self, entity: Entity) -> impl Iterator<Item = &str> + '_ {
+        self.entities
+            .get(&entity)
+            .into_iter()
+            .flat_map(|components| components.keys().map(String::as_str))
+    }
+
+    /// Get the captured value of a component of an entity.
+    pub fn get(&self, entity: Entity, component: &str) -> Option<&dyn Reflect> {
+        self.entities
+            .get(&entity)?
+            .get(component)
+            .map(|value| value.as_ref())
+    }
+
+    /// Make the list of diffs transforming this snapshot into `curr`.
+    ///
+    /// Entities are visited in increasing order, and for each entity its
+    /// components are visited in increasing type path order. This produces,
+    /// per entity:
+    /// - for a spawned entity, a [`DiffContent::Spawn`] diff followed by one
+    ///   [`DiffContent::Insert`] diff per component;
+    /// - for a despawned entity, a [`DiffContent::Despawn`] diff;
+    /// - otherwise, one diff per component inserted, removed, or with changed
+    ///   fields.
+    ///
+    /// Applying in order the returned diffs with [`Diff::apply_world_mapped()`]
+    /// to a world matching this snapshot produces a world matching `curr`.
+    ///
+    /// Values are serialized with `codec`.
+    pub fn diff(
+        &self,
+        curr: &Snapshot,
+        registry: &TypeRegistry,
+        codec: Codec,
+    ) -> Result<Vec<Diff>, Error> {
+        self.diff_impl(curr, registry, false, codec)
+    }
+
+    /// Make the list of two-way diffs transforming this snapshot into `curr`.
+    ///
+    /// This is similar to [`diff()`], but all diffs are two-way. In particular
+    /// the components of a despawned entity are recorded with
+    /// [`DiffContent::Remove`] diffs before the [`DiffContent::Despawn`] one,
+    /// so that inverting the diffs restores the entity with all its components.
+    /// The changes can be reverted by applying the inverted diffs in reverse
+    /// order.
+    ///
+    /// [`diff()`]: Snapshot::diff
+    pub fn diff_two_way(
+        &self,
+        curr: &Snapshot,
+        registry: &TypeRegistry,
+        codec: Codec,
+    ) -> Result<Vec<Diff>, Error> {
+        self.diff_impl(curr, registry, true, codec)
+    }
+
+    fn diff_impl(
+        &self,
+        curr: &Snapshot,
+        registry: &TypeRegistry,
+        two_way: bool,
+        codec: Codec,
+    ) -> Result<Vec<Diff>, Error> {
+        let mut diffs = vec![];
+        let empty = BTreeMap::new();
+        let entities: BTreeSet<Entity> = self.entities().chain(curr.entities()).collect();
+        for entity in entities {
+            let base_components = self.entities.get(&entity);
+            let curr_components = curr.entities.get(&entity);
+            if base_components.is_none() {
+                diffs.push(Diff::new(
+                    DiffTarget::from_entity(entity),
+                    vec![DiffContent::Spawn],
+                ));
+            }
+            if curr_components.is_some() || two_way {
+                let base_components = base_components.unwrap_or(&empty);
+                let curr_components = curr_components.unwrap_or(&empty);
+                let components: BTreeSet<&String> = base_components
+                    .keys()
+                    .chain(curr_components.keys())
+                    .collect();
+                for component in components {
+                    let target = DiffTarget::new(entity, component.clone());
+                    let content = match (
+                        base_components.get(component),
+                        curr_components.get(component),
+                    ) {
+                        (None, Some(curr)) => DiffContent::Insert(DiffData::new(
+                            String::new(),
+                            curr.as_ref(),
+                            registry,
+                            codec,
+                        )?),
+                        (Some(base), None) => DiffContent::Remove(if two_way {
+                            Some(DiffData::new(
+                                String::new(),
+                                base.as_ref(),
+                                registry,
+                                codec,
+                            )?)
+                        } else {
+                            None
+                        }),
+                        (Some(base), Some(curr)) => {
+                            let diff = Diff::make_impl(
+                                base.as_ref(),
+                                curr.as_ref(),
+                                registry,
+                                two_way,
+                                codec,
+                            )?;
+                            if !diff.is_empty() {
+                                diffs.push(diff.with_target(target));
+                            }
+                            continue;
+                        }
+                        (None, None) => unreachable!(),
+                    };
+                    diffs.push(Diff::new(target, vec![content]));
+                }
+            }
+            if curr_components.is_none() {
+                diffs.push(Diff::new(
+                    DiffTarget::from_entity(entity),
+                    vec![DiffContent::Despawn],
+                ));
+            }
+        }
+        Ok(diffs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::ecs::{component::Component, entity::EntityHashMap};
+    use bevy::reflect::TypePath;
+
+    #[derive(Debug, Clone, PartialEq, Component, Reflect)]
+    #[reflect(Component)]
+    struct C {
+        f: f32,
+        i: i32,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Component, Reflect)]
+    #[reflect(Component)]
+    struct Tag(u32);
+
+    #[derive(Component)]
+    struct NotReflected;
+
+    fn registry() -> TypeRegistry {
+        let mut registry = TypeRegistry::default();
+        registry.register::<C>();
+        registry.register::<Tag>();
+        registry
+    }
+
+    /// Check that the world contains the same entities and components as the
+    /// snapshot, once mapped.
+    fn assert_matches(
+        world: &World,
+        expected: &Snapshot,
+        entity_map: &EntityHashMap<Entity>,
+        registry: &TypeRegistry,
+    ) {
+        let actual = Snapshot::capture(world, registry);
+        assert_eq!(actual.entities().count(), expected.entities().count());
+        for entity in expected.entities() {
+            let mapped = entity_map.get(&entity).copied().unwrap_or(entity);
+            assert!(actual.components(mapped).eq(expected.components(entity)));
+            for component in expected.components(entity) {
+                let value = actual.get(mapped, component).unwrap();
+                let expected = expected.get(entity, component).unwrap();
+                assert_eq!(value.reflect_partial_eq(expected), Some(true));
+            }
+        }
+    }
+
+    fn spawn_base(world: &mut World) -> [Entity; 3] {
+        [
+            world.spawn(C { f: 1., i: 1 }).id(),
+            world.spawn((C { f: 2., i: 2 }, Tag(2), NotReflected)).id(),
+            world.spawn(Tag(3)).id(),
+        ]
+    }
+
+    fn modify(world: &mut World, [e1, e2, e3]: [Entity; 3]) -> Entity {
+        world.get_mut::<C>(e1).unwrap().f = 5.;
+        world.entity_mut(e1).insert(Tag(1));
+        world.entity_mut(e2).remove::<Tag>();
+        world.despawn(e3);
+        world.spawn(C { f: 4., i: 4 }).id()
+    }
+
+    #[test]
+    fn capture() {
+        let registry = registry();
+        let mut world = World::new();
+        let [e1, e2, e3] = spawn_base(&mut world);
+        let snapshot = Snapshot::capture(&world, &registry);
+        assert!(snapshot.entities().eq([e1, e2, e3]));
+        assert!(snapshot.components(e1).eq([C::type_path()]));
+        assert!(snapshot
+            .components(e2)
+            .eq([C::type_path(), Tag::type_path()]));
+        assert_eq!(
+            snapshot
+                .get(e2, C::type_path())
+                .unwrap()
+                .reflect_partial_eq(&C { f: 2., i: 2 }),
+            Some(true)
+        );
+
+        let snapshot = Snapshot::capture_entities(&world, [e3, Entity::from_raw(42)], &registry);
+        assert!(snapshot.entities().eq([e3]));
+        assert!(!snapshot.contains(e1));
+    }
+
+    #[test]
+    fn diff() {
+        let registry = registry();
+        let mut world = World::new();
+        let entities = spawn_base(&mut world);
+        let [e1, e2, e3] = entities;
+        let base = Snapshot::capture(&world, &registry);
+        let e4 = modify(&mut world, entities);
+        let curr = Snapshot::capture(&world, &registry);
+
+        let diffs = base.diff(&curr, &registry, Codec::Ron).unwrap();
+        let summary: Vec<_> = diffs
+            .iter()
+            .map(|diff| {
+                let target = diff.target().unwrap();
+                let kind = match &diff.content()[0] {
+                    DiffContent::Single(data) => data.path().to_string(),
+                    DiffContent::Insert(_) => "insert".to_string(),
+                    DiffContent::Remove(_) => "remove".to_string(),
+                    DiffContent::Spawn => "spawn".to_string(),
+                    DiffContent::Despawn => "despawn".to_string(),
+                    DiffContent::Dual(_, _) => unreachable!(),
+                };
+                (target.entity(), target.component(), kind)
+            })
+            .collect();
+        let c = Some(C::type_path());
+        let tag = Some(Tag::type_path());
+        let mut expected = vec![
+            (e1, c, ".f".to_string()),
+            (e1, tag, "insert".to_string()),
+            (e2, tag, "remove".to_string()),
+            (e3, None, "despawn".to_string()),
+            (e4, None, "spawn".to_string()),
+            (e4, c, "insert".to_string()),
+        ];
+        // e4 may reuse the index of e3
+        expected.sort_by_key(|(entity, _, _)| *entity);
+        assert_eq!(summary, expected);
+
+        // Apply to a copy of the original world
+        let mut copy = World::new();
+        spawn_base(&mut copy);
+        let mut entity_map = EntityHashMap::default();
+        for diff in &diffs {
+            diff.apply_world_mapped(&mut copy, &registry, &mut entity_map)
+                .unwrap();
+        }
+        assert_matches(&copy, &curr, &entity_map, &registry);
+    }
+
+    #[test]
+    fn diff_two_way() {
+        let registry = registry();
+        let mut world = World::new();
+        let entities = spawn_base(&mut world);
+        let base = Snapshot::capture(&world, &registry);
+        modify(&mut world, entities);
+        let curr = Snapshot::capture(&world, &registry);
+
+        let diffs = base.diff_two_way(&curr, &registry, Codec::Ron).unwrap();
+        assert!(diffs.iter().all(Diff::is_two_way));
+
+        let mut copy = World::new();
+        spawn_base(&mut copy);
+        let mut entity_map = EntityHashMap::default();
+        for diff in &diffs {
+            diff.apply_world_mapped(&mut copy, &registry, &mut entity_map)
+                .unwrap();
+        }
+        assert_matches(&copy, &curr, &entity_map, &registry);
+
+        for diff in diffs.iter().rev() {
+            diff.invert()
+                .unwrap()
+                .apply_world_mapped(&mut copy, &registry, &mut entity_map)
+                .unwrap();
+        }
+        assert_matches(&copy, &base, &entity_map, &registry);
+    }
+}
@@ -0,0 +1,220 @@
+use std::io::{Read, Write};
+use std::net::{Shutdown, TcpStream, ToSocketAddrs};
+use std::sync::mpsc;
+
+use super::error::Error;
+use super::framing::{FrameReader, FrameWriter, DEFAULT_MAX_FRAME_SIZE};
+
+/// Sending half of a [`Transport`].
+pub trait FrameSender: Send {
+    /// Send a single frame.
+    fn send_frame(&mut self, frame: &[u8]) -> Result<(), Error>;
+}
+
+/// Receiving half of a [`Transport`].
+pub trait FrameReceiver: Send {
+    /// Receive the next frame, blocking until it's available.
+    ///
+    /// Returns `None` once the remote end closed the connection.
+    fn recv_frame(&mut self) -> Result<Option<Vec<u8>>, Error>;
+}
+
+impl<S: FrameSender + ?Sized> FrameSender for Box<S> {
+    fn send_frame(&mut self, frame: &[u8]) -> Result<(), Error> {
+        (**self).send_frame(frame)
+    }
+}
+
+impl<R: FrameReceiver + ?Sized> FrameReceiver for Box<R> {
+    fn recv_frame(&mut self) -> Result<Option<Vec<u8>>, Error> {
+        (**self).recv_frame()
+    }
+}
+
+impl<W: Write + Send> FrameSender for FrameWriter<W> {
+    fn send_frame(&mut self, frame: &[u8]) -> Result<(), Error> {
+        self.write_frame(frame)
+    }
+}
+
+impl<R: Read + Send> FrameReceiver for FrameReader<R> {
+    fn recv_frame(&mut self) -> Result<Option<Vec<u8>>, Error> {
+        self.read_frame()
+    }
+}
+
+/// Sending and receiving halves of a [`Transport`].
+pub type TransportHalves = (Box<dyn FrameSender>, Box<dyn FrameReceiver>);
+
+/// Bidirectional connection exchanging frames with a remote peer.
+///
+/// The transport abstracts the medium used to exchange messages, which can be
+/// in-process ([`ChannelTransport`]) for solo editing, or a network connection
+/// ([`TcpTransport`]) to edit a running game or for multi-user editing. A
+/// transport is used by splitting it into its sending and receiving halves,
+/// which can be used independently from different threads.
+pub trait Transport: Send + 'static {
+    /// Split the transport into its sending and receiving halves.
+    fn split(self: Box<Self>) -> Result<TransportHalves, Error>;
+}
+
+/// In-process transport exchanging frames over channels.
+///
+/// Transports are created in connected pairs with [`pair()`]; frames sent by
+/// one transport are received by the other one.
+///
+/// [`pair()`]: ChannelTransport::pair
+pub struct ChannelTransport {
+    sender: ChannelSender,
+    receiver: ChannelReceiver,
+}
+
+impl ChannelTransport {
+    /// Create a pair of connected transports.
+    pub fn pair() -> (ChannelTransport, ChannelTransport) {
+        let (sender0, receiver0) = mpsc::channel();
+        let (sender1, receiver1) = mpsc::channel();
+        (
+            ChannelTransport {
+                sender: ChannelSender(sender0),
+                receiver: ChannelReceiver(receiver1),
+            },
+            ChannelTransport {
+                sender: ChannelSender(sender1),
+                receiver: ChannelReceiver(receiver0),
+            },
+        )
+    }
+}
+
+impl Transport for ChannelTransport {
+    fn split(self: Box<Self>) -> Result<TransportHalves, Error> {
+        Ok((Box::new(self.sender), Box::new(self.receiver)))
+    }
+}
+
+/// Sending half of a [`ChannelTransport`].
+pub struct ChannelSender(mpsc::Sender<Vec<u8>>);
+
+impl FrameSender for ChannelSender {
+    fn send_frame(&mut self, frame: &[u8]) -> Result<(), Error> {
+        self.0.send(frame.to_vec()).map_err(|_| Error::Disconnected)
+    }
+}
+
+/// Receiving half of a [`ChannelTransport`].
+pub struct ChannelReceiver(mpsc::Receiver<Vec<u8>>);
+
+impl FrameReceiver for ChannelReceiver {
+    fn recv_frame(&mut self) -> Result<Option<Vec<u8>>, Error> {
+        Ok(self.0.recv().ok())
+    }
+}
+
+/// Transport exchanging length-prefixed frames over a TCP connection.
+///
+/// See [`FrameDecoder`] for the frame format.
+///
+/// [`FrameDecoder`]: crate::framing::FrameDecoder
+pub struct TcpTransport {
+    stream: TcpStream,
+    max_frame_size: usize,
+}
+
+impl TcpTransport {
+    /// Create a transport from a connected stream.
+    ///
+    /// This disables Nagle's algorithm on the stream, to reduce the latency of
+    /// small messages.
+    pub fn new(stream: TcpStream) -> Result<Self, Error> {
+        stream.set_nodelay(true)?;
+        Ok(Self {
+            stream,
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+        })
+    }
+
+    /// Connect to a remote peer.
+    pub fn connect(addr: impl ToSocketAddrs) -> Result<Self, Error> {
+        Self::new(TcpStream::connect(addr)?)
+    }
+
+    /// Set the maximum size in bytes of the frames sent and received.
+    pub fn with_max_frame_size(mut self, max_frame_size: usize) -> Self {
+        self.max_frame_size = max_frame_size;
+        self
+    }
+}
+
+impl Transport for TcpTransport {
+    fn split(self: Box<Self>) -> Result<TransportHalves, Error> {
+        let reader =
+            FrameReader::new(self.stream.try_clone()?).with_max_frame_size(self.max_frame_size);
+        let writer = FrameWriter::new(self.stream).with_max_frame_size(self.max_frame_size);
+        Ok((Box::new(TcpSender(writer)), Box::new(reader)))
+    }
+}
+
+/// Sending half of a [`TcpTransport`].
+///
+/// Dropping the sender shuts down the sending side of the connection, which
+/// lets the remote peer know that no more frame will be sent.
+struct TcpSender(FrameWriter<TcpStream>);
+
+impl FrameSender for TcpSender {
+    fn send_frame(&mut self, frame: &[u8]) -> Result<(), Error> {
+        self.0.write_frame(frame)
+    }
+}
+
+impl Drop for TcpSender {
+    fn drop(&mut self) {
+        let _ = self.0.get_ref().shutdown(Shutdown::Write);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    fn ping_pong(a: impl Transport, b: impl Transport) {
+        let (mut a_send, mut a_recv) = Box::new(a).split().unwrap();
+        let (mut b_send, mut b_recv) = Box::new(b).split().unwrap();
+        let echo = std::thread::spawn(move || {
+            while let Some(frame) = b_recv.recv_frame().unwrap() {
+                b_send.send_frame(&frame).unwrap();
+            }
+        });
+        for frame in [&b"ping"[..], b"", b"pong"] {
+            a_send.send_frame(frame).unwrap();
+            assert_eq!(a_recv.recv_frame().unwrap().unwrap(), frame);
+        }
+        drop(a_send);
+        echo.join().unwrap();
+        assert_eq!(a_recv.recv_frame().unwrap(), None);
+    }
+
+    #[test]
+    fn channel() {
+        let (a, b) = ChannelTransport::pair();
+        ping_pong(a, b);
+
+        let (a, b) = ChannelTransport::pair();
+        let (mut sender, _) = Box::new(a).split().unwrap();
+        drop(b);
+        assert!(matches!(
+            sender.send_frame(b"ping"),
+            Err(Error::Disconnected)
+        ));
+    }
+
+    #[test]
+    fn tcp() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let client = std::thread::spawn(move || TcpTransport::connect(addr).unwrap());
+        let server = TcpTransport::new(listener.accept().unwrap().0).unwrap();
+        ping_pong(client.join().unwrap(), server);
+    }
+}
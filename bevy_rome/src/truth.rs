@@ -0,0 +1,913 @@
+use bevy::app::{App, Plugin, PostUpdate};
+use bevy::ecs::{
+    component::Component,
+    entity::Entity,
+    event::{Event, Events},
+    reflect::{AppTypeRegistry, ReflectComponent},
+    schedule::{IntoSystemConfigs, SystemSet},
+    system::Resource,
+    world::{Mut, World},
+};
+use bevy::log::warn;
+use bevy::reflect::{Reflect, TypePath, TypeRegistry};
+use bevy::utils::{HashMap, Uuid};
+use serde::{Deserialize, Serialize};
+use std::any::Any;
+use std::collections::BTreeSet;
+use std::sync::{mpsc, Mutex};
+
+use super::capture::{capture_diffs, DiffCaptureState, DiffEvent, ReflectDiffCapture};
+use super::codec::Codec;
+use super::diff::{Diff, DiffData};
+use super::entity::{EntityId, EntityMap};
+use super::error::Error;
+use super::history::UndoHistory;
+use super::mirror::EntityDiff;
+use super::snapshot::Snapshot;
+use super::{AnyMessage, FieldPath, SetFieldMsg};
+
+/// Component present on each object of a [`Truth`].
+///
+/// The properties of an object are the other reflected components of its
+/// entity, whose types must be registered with the [`ReflectComponent`] and
+/// [`ReflectDiffCapture`] type data to be captured in change notifications.
+#[derive(Debug, Default, Clone, PartialEq, Component, Reflect)]
+#[reflect(Component, DiffCapture)]
+pub struct Object {
+    /// Name of the type of the object, like `"Material"`.
+    pub object_type: String,
+    /// Parent object, if any.
+    pub parent: Option<EntityId>,
+}
+
+impl Object {
+    /// Create a new object of the given type, without parent.
+    pub fn new(object_type: impl Into<String>) -> Self {
+        Self {
+            object_type: object_type.into(),
+            parent: None,
+        }
+    }
+
+    /// Set the parent object.
+    pub fn with_parent(mut self, parent: EntityId) -> Self {
+        self.parent = Some(parent);
+        self
+    }
+}
+
+/// Message creating or deleting an object of a [`Truth`].
+///
+/// Like other messages, the message toggles the object when applied: if the
+/// object exists it's deleted, and its components are saved into the message;
+/// otherwise it's created with the saved components. The target entity of the
+/// message is ignored; the object is identified by its [`EntityId`], found
+/// with the [`EntityMap`] resource of the world.
+///
+/// Deleting an object only removes its components; the entity itself is kept
+/// with its [`EntityId`], so that other messages targetting it stay valid once
+/// the deletion is undone.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, TypePath)]
+pub struct ObjectMsg {
+    id: EntityId,
+    /// Type path and value of each component of the object, if it's deleted.
+    components: Vec<(String, DiffData)>,
+    exists: bool,
+    /// Format of the saved components.
+    codec: Codec,
+}
+
+impl ObjectMsg {
+    /// Create a message creating an object with the given components,
+    /// serialized with `codec`.
+    pub fn create(
+        id: EntityId,
+        object: &Object,
+        components: &[&dyn Reflect],
+        registry: &TypeRegistry,
+        codec: Codec,
+    ) -> Result<Self, Error> {
+        let components = std::iter::once(object as &dyn Reflect)
+            .chain(components.iter().copied())
+            .map(|value| {
+                let data = DiffData::new(String::new(), value, registry, codec)?;
+                Ok((value.reflect_type_path().to_string(), data))
+            })
+            .collect::<Result<_, Error>>()?;
+        Ok(Self {
+            id,
+            components,
+            exists: false,
+            codec,
+        })
+    }
+
+    /// Create a message deleting an object, saving its components serialized
+    /// with `codec`.
+    pub fn delete(id: EntityId, codec: Codec) -> Self {
+        Self {
+            id,
+            components: vec![],
+            exists: true,
+            codec,
+        }
+    }
+
+    /// Identifier of the object.
+    pub fn id(&self) -> EntityId {
+        self.id
+    }
+
+    fn toggle(&mut self, world: &mut World) -> Result<(), Error> {
+        let registry = world.resource::<AppTypeRegistry>().clone();
+        let registry = registry.read();
+        if self.exists {
+            let entity = world.resource::<EntityMap>().resolve(self.id)?;
+            let mut components = vec![];
+            let mut reflect_components = vec![];
+            let entity_ref = world.entity(entity);
+            for component_id in entity_ref.archetype().components() {
+                let Some(registration) = world
+                    .components()
+                    .get_info(component_id)
+                    .and_then(|info| info.type_id())
+                    .and_then(|type_id| registry.get(type_id))
+                else {
+                    continue;
+                };
+                let Some(reflect_component) = registration.data::<ReflectComponent>() else {
+                    continue;
+                };
+                let type_path = registration.type_info().type_path();
+                if type_path == EntityId::type_path() {
+                    continue;
+                }
+                let value = reflect_component.reflect(entity_ref).unwrap();
+                let data = DiffData::new(String::new(), value, &registry, self.codec)?;
+                components.push((type_path.to_string(), data));
+                reflect_components.push(reflect_component.clone());
+            }
+            let mut entity_mut = world.entity_mut(entity);
+            for reflect_component in reflect_components {
+                reflect_component.remove(&mut entity_mut);
+            }
+            self.components = components;
+        } else {
+            let values = self
+                .components
+                .iter()
+                .map(|(type_path, data)| {
+                    let registration = registry
+                        .get_with_type_path(type_path)
+                        .ok_or_else(|| Error::UnregisteredType(type_path.clone()))?;
+                    let reflect_component = registration
+                        .data::<ReflectComponent>()
+                        .ok_or_else(|| Error::UnregisteredType(type_path.clone()))?;
+                    Ok((
+                        reflect_component,
+                        data.deserialize(registration, &registry)?,
+                    ))
+                })
+                .collect::<Result<Vec<_>, Error>>()?;
+            let entity = match world.resource::<EntityMap>().entity(self.id) {
+                Some(entity) => entity,
+                None => {
+                    let entity = world.spawn(self.id).id();
+                    world.resource_mut::<EntityMap>().insert(self.id, entity);
+                    entity
+                }
+            };
+            let mut entity_mut = world.entity_mut(entity);
+            for (reflect_component, value) in values {
+                reflect_component.insert(&mut entity_mut, value.as_ref(), &registry);
+            }
+            self.components.clear();
+        }
+        self.exists = !self.exists;
+        Ok(())
+    }
+}
+
+impl AnyMessage for ObjectMsg {
+    fn undo(&mut self, world: &mut World, _entity: Entity) -> Result<(), Error> {
+        self.toggle(world)
+    }
+
+    fn redo(&mut self, world: &mut World, _entity: Entity) -> Result<(), Error> {
+        self.toggle(world)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// Event notifying the changes applied to a [`Truth`] by [`Truth::apply()`].
+#[derive(Debug, Clone, PartialEq, Event)]
+pub struct TruthChanged {
+    /// Version of the truth after the changes.
+    pub version: u64,
+    /// Diffs of the changed objects, identified by their [`EntityId`].
+    ///
+    /// Deleting an object produces a [`DiffContent::Remove`] diff for each of
+    /// its components, and creating one a [`DiffContent::Insert`] diff.
+    ///
+    /// [`DiffContent::Remove`]: crate::diff::DiffContent::Remove
+    /// [`DiffContent::Insert`]: crate::diff::DiffContent::Insert
+    pub diffs: Vec<EntityDiff>,
+}
+
+/// Filter selecting the changes of a [`Truth`] received by a [`Subscription`].
+///
+/// A filter has three sets of criteria: object types, objects, and fields. A
+/// diff matches the filter if it matches at least one criterion of each
+/// non-empty set; an empty filter matches all diffs. Field criteria select the
+/// individual changes of a diff, by overlapping [`FieldPath`]; use an empty
+/// path to select a whole component, like `FieldPath::of::<Object>("")` for
+/// the creation, deletion and reparenting of objects.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct SubscriptionFilter {
+    object_types: Vec<String>,
+    objects: Vec<EntityId>,
+    fields: Vec<FieldPath>,
+}
+
+impl SubscriptionFilter {
+    /// Create an empty filter, matching all diffs.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add an object type to match.
+    pub fn with_object_type(mut self, object_type: impl Into<String>) -> Self {
+        self.object_types.push(object_type.into());
+        self
+    }
+
+    /// Add an object to match.
+    pub fn with_object(mut self, id: EntityId) -> Self {
+        self.objects.push(id);
+        self
+    }
+
+    /// Add a component field to match.
+    pub fn with_field(mut self, field: FieldPath) -> Self {
+        self.fields.push(field);
+        self
+    }
+
+    /// Filter the diff of an object of the given type.
+    ///
+    /// Returns the diff with only the changes matching the filter, or `None`
+    /// if no change matches.
+    pub fn filter(
+        &self,
+        object_type: Option<&str>,
+        entity_diff: &EntityDiff,
+    ) -> Option<EntityDiff> {
+        if !self.objects.is_empty() && !self.objects.contains(&entity_diff.id) {
+            return None;
+        }
+        if !self.object_types.is_empty()
+            && !object_type
+                .is_some_and(|object_type| self.object_types.iter().any(|t| t == object_type))
+        {
+            return None;
+        }
+        if self.fields.is_empty() {
+            return Some(entity_diff.clone());
+        }
+        let target = entity_diff.diff.target()?;
+        let component = target.component()?;
+        let content: Vec<_> = entity_diff
+            .diff
+            .content()
+            .iter()
+            .filter(|content| {
+                content.path().is_some_and(|path| {
+                    let field = FieldPath::new(component, path);
+                    self.fields.iter().any(|f| f.overlaps(&field))
+                })
+            })
+            .cloned()
+            .collect();
+        if content.is_empty() {
+            return None;
+        }
+        Some(EntityDiff {
+            id: entity_diff.id,
+            diff: Diff::new(target.clone(), content),
+        })
+    }
+}
+
+/// Stream of the changes of a [`Truth`] matching a [`SubscriptionFilter`].
+///
+/// Created by [`Truth::subscribe()`]. Each [`TruthChanged`] notification with
+/// at least one matching diff is received with only its matching diffs, and
+/// keeps the version of the original notification. Dropping the subscription
+/// unsubscribes it.
+pub struct Subscription {
+    receiver: Mutex<mpsc::Receiver<TruthChanged>>,
+}
+
+impl Subscription {
+    /// Receive the next change, blocking until it's available.
+    ///
+    /// Returns `None` once the truth was dropped.
+    pub fn recv(&self) -> Option<TruthChanged> {
+        self.receiver.lock().unwrap().recv().ok()
+    }
+
+    /// Receive the next change if any, without blocking.
+    pub fn try_recv(&self) -> Option<TruthChanged> {
+        self.receiver.lock().unwrap().try_recv().ok()
+    }
+
+    /// Receive all the changes already available, without blocking.
+    pub fn drain(&self) -> Vec<TruthChanged> {
+        self.receiver.lock().unwrap().try_iter().collect()
+    }
+}
+
+/// Snapshot of a [`Truth`] at a given version.
+#[derive(Debug)]
+pub struct VersionedSnapshot {
+    /// Version of the truth when the snapshot was captured.
+    pub version: u64,
+    /// Snapshot of the objects.
+    pub snapshot: Snapshot,
+}
+
+/// Editor data model, a store of typed objects independent of the game world.
+///
+/// Each object has a stable [`EntityId`], an [`Object`] component with its type
+/// and parent, and any number of other reflected components forming its
+/// properties. Objects are stored as entities of a [`World`] private to the
+/// truth, which can only be read; all mutations are messages pushed into the
+/// [`UndoHistory`] of the truth, and can be undone. Pending mutations are
+/// executed by [`apply()`], which increments the version of the truth and
+/// records a [`TruthChanged`] notification with the diffs of the changed
+/// objects. Extensions observe the changes they're interested in with a
+/// [`Subscription`].
+///
+/// [`apply()`]: Truth::apply
+#[derive(Resource)]
+pub struct Truth {
+    world: World,
+    history: UndoHistory,
+    codec: Codec,
+    version: u64,
+    changes: Vec<TruthChanged>,
+    /// Last known type of each object, including deleted ones.
+    object_types: HashMap<EntityId, String>,
+    /// Parent of each existing object which has one.
+    parents: HashMap<EntityId, EntityId>,
+    /// Children of each existing object which has some.
+    children: HashMap<EntityId, BTreeSet<EntityId>>,
+    subscribers: Vec<(SubscriptionFilter, mpsc::Sender<TruthChanged>)>,
+}
+
+impl Truth {
+    /// Create an empty truth, using the given type registry.
+    ///
+    /// This registers the [`Object`] component and the types it depends on.
+    pub fn new(registry: AppTypeRegistry) -> Self {
+        {
+            let mut registry = registry.write();
+            registry.register::<Object>();
+            registry.register::<EntityId>();
+            registry.register::<Option<EntityId>>();
+            registry.register::<Uuid>();
+            registry.register::<String>();
+        }
+        let mut world = World::new();
+        world.insert_resource(registry);
+        world.init_resource::<EntityMap>();
+        world.init_resource::<Events<DiffEvent>>();
+        world.insert_resource(DiffCaptureState::new(true, Codec::Ron));
+        Self {
+            world,
+            history: UndoHistory::default(),
+            codec: Codec::Ron,
+            version: 0,
+            changes: vec![],
+            object_types: HashMap::default(),
+            parents: HashMap::default(),
+            children: HashMap::default(),
+            subscribers: vec![],
+        }
+    }
+
+    /// Set the format of the values recorded by mutations and change
+    /// notifications, by default [`Codec::Ron`].
+    pub fn with_codec(mut self, codec: Codec) -> Self {
+        self.codec = codec;
+        self.world
+            .insert_resource(DiffCaptureState::new(true, codec));
+        self
+    }
+
+    /// Format of the values recorded by mutations and change notifications.
+    pub fn codec(&self) -> Codec {
+        self.codec
+    }
+
+    /// The world storing the objects.
+    pub fn world(&self) -> &World {
+        &self.world
+    }
+
+    /// Current version, incremented by each call to [`apply()`] which changed
+    /// any object.
+    ///
+    /// [`apply()`]: Truth::apply
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    /// The history of the mutations, to undo or redo them, or group them into
+    /// transactions.
+    pub fn history(&self) -> &UndoHistory {
+        &self.history
+    }
+
+    /// Mutable access to the history of the mutations, to undo or redo them, or
+    /// group them into transactions.
+    pub fn history_mut(&mut self) -> &mut UndoHistory {
+        &mut self.history
+    }
+
+    /// Get the entity of an existing object, if any.
+    pub fn entity(&self, id: EntityId) -> Option<Entity> {
+        let entity = self.world.resource::<EntityMap>().entity(id)?;
+        self.world.get::<Object>(entity).map(|_| entity)
+    }
+
+    /// Get an existing object, if any.
+    pub fn object(&self, id: EntityId) -> Option<&Object> {
+        self.get::<Object>(id)
+    }
+
+    /// Get a component of an existing object, if any.
+    pub fn get<T: Component>(&self, id: EntityId) -> Option<&T> {
+        self.world.get::<T>(self.entity(id)?)
+    }
+
+    /// Iterate over all existing objects, in no particular order.
+    pub fn objects(&self) -> impl Iterator<Item = (EntityId, &Object)> + '_ {
+        self.world
+            .iter_entities()
+            .filter_map(|entity| Some((*entity.get::<EntityId>()?, entity.get::<Object>()?)))
+    }
+
+    /// Type of an object, including a deleted one, if ever created.
+    pub fn object_type(&self, id: EntityId) -> Option<&str> {
+        self.object_types.get(&id).map(String::as_str)
+    }
+
+    /// Identifiers of the children of an object, sorted by identifier.
+    pub fn children(&self, id: EntityId) -> Vec<EntityId> {
+        self.children
+            .get(&id)
+            .map(|children| children.iter().copied().collect())
+            .unwrap_or_default()
+    }
+
+    /// Identifiers of the descendants of an object, children before their
+    /// parent.
+    fn descendants(&self, id: EntityId, descendants: &mut Vec<EntityId>) {
+        for child in self.children(id) {
+            self.descendants(child, descendants);
+            descendants.push(child);
+        }
+    }
+
+    /// Resolve an existing object, or return an [`Error::UnmappedEntity`].
+    fn resolve(&self, id: EntityId) -> Result<Entity, Error> {
+        self.entity(id).ok_or(Error::UnmappedEntity(id))
+    }
+
+    /// Queue creating a new object with the given components, and return its
+    /// identifier.
+    ///
+    /// The parent of the object, if any, must be an existing object, otherwise
+    /// this returns an [`Error::InvalidParent`]. The object is only created by
+    /// the next call to [`apply()`]. The types of the components must be
+    /// registered with their [`ReflectComponent`] type data.
+    ///
+    /// [`apply()`]: Truth::apply
+    pub fn create(
+        &mut self,
+        object: Object,
+        components: &[&dyn Reflect],
+    ) -> Result<EntityId, Error> {
+        let id = EntityId::new();
+        if let Some(parent) = object.parent {
+            if self.entity(parent).is_none() {
+                return Err(Error::InvalidParent { object: id, parent });
+            }
+        }
+        let message = {
+            let registry = self.world.resource::<AppTypeRegistry>().read();
+            ObjectMsg::create(id, &object, components, &registry, self.codec)?
+        };
+        self.history
+            .push_dyn(Entity::PLACEHOLDER, Box::new(message));
+        Ok(id)
+    }
+
+    /// Queue deleting an existing object and all its descendants, as a single
+    /// transaction.
+    pub fn delete(&mut self, id: EntityId) -> Result<(), Error> {
+        self.resolve(id)?;
+        let mut ids = vec![];
+        self.descendants(id, &mut ids);
+        ids.push(id);
+        self.history.begin_transaction();
+        for id in ids {
+            self.history.push_dyn(
+                Entity::PLACEHOLDER,
+                Box::new(ObjectMsg::delete(id, self.codec)),
+            );
+        }
+        self.history.commit_transaction();
+        Ok(())
+    }
+
+    /// Queue setting the field at `path` of the component `T` of an existing
+    /// object to `value`.
+    pub fn set<T: Component + TypePath>(
+        &mut self,
+        id: EntityId,
+        path: &str,
+        value: &dyn Reflect,
+    ) -> Result<(), Error> {
+        let entity = self.resolve(id)?;
+        let message = {
+            let registry = self.world.resource::<AppTypeRegistry>().read();
+            SetFieldMsg::of::<T>(path, value, &registry, self.codec)?
+        };
+        self.history.push_dyn(entity, Box::new(message));
+        Ok(())
+    }
+
+    /// Queue changing the parent of an existing object.
+    ///
+    /// The parent must be an existing object, and not a descendant of the
+    /// object, otherwise this returns an [`Error::InvalidParent`].
+    pub fn set_parent(&mut self, id: EntityId, parent: Option<EntityId>) -> Result<(), Error> {
+        if let Some(parent) = parent {
+            let mut descendants = vec![id];
+            self.descendants(id, &mut descendants);
+            if self.entity(parent).is_none() || descendants.contains(&parent) {
+                return Err(Error::InvalidParent { object: id, parent });
+            }
+        }
+        self.set::<Object>(id, ".parent", &parent)
+    }
+
+    /// Queue applying any message to an existing object.
+    pub fn push(&mut self, id: EntityId, message: Box<dyn AnyMessage>) -> Result<(), Error> {
+        let entity = self.resolve(id)?;
+        self.history.push_dyn(entity, message);
+        Ok(())
+    }
+
+    /// Execute all pending mutations and history operations.
+    ///
+    /// If any object changed, this increments the version and records a
+    /// [`TruthChanged`] notification, retrieved with [`drain_changes()`]. The
+    /// notification is recorded even if an operation fails, for the changes of
+    /// the operations executed before it.
+    ///
+    /// [`drain_changes()`]: Truth::drain_changes
+    pub fn apply(&mut self) -> Result<(), Error> {
+        let result = self.history.apply(&mut self.world);
+        capture_diffs(&mut self.world);
+        self.world.clear_trackers();
+        let diffs: Vec<_> = self
+            .world
+            .resource_mut::<Events<DiffEvent>>()
+            .drain()
+            .collect();
+        let diffs: Vec<EntityDiff> = diffs
+            .into_iter()
+            .filter_map(|DiffEvent(diff)| {
+                let entity = diff.target()?.entity();
+                let id = *self.world.get::<EntityId>(entity)?;
+                Some(EntityDiff { id, diff })
+            })
+            .collect();
+        if !diffs.is_empty() {
+            for entity_diff in &diffs {
+                if let Some(object) = self.object(entity_diff.id) {
+                    self.object_types
+                        .insert(entity_diff.id, object.object_type.clone());
+                }
+                let parent = self.object(entity_diff.id).and_then(|object| object.parent);
+                self.reparent(entity_diff.id, parent);
+            }
+            self.version += 1;
+            let change = TruthChanged {
+                version: self.version,
+                diffs,
+            };
+            self.notify(&change);
+            self.changes.push(change);
+        }
+        result
+    }
+
+    /// Update the index of the children after the parent of an object changed,
+    /// or the object was created or deleted.
+    fn reparent(&mut self, id: EntityId, parent: Option<EntityId>) {
+        let old_parent = match parent {
+            Some(parent) => self.parents.insert(id, parent),
+            None => self.parents.remove(&id),
+        };
+        if old_parent == parent {
+            return;
+        }
+        if let Some(old_parent) = old_parent {
+            if let Some(children) = self.children.get_mut(&old_parent) {
+                children.remove(&id);
+                if children.is_empty() {
+                    self.children.remove(&old_parent);
+                }
+            }
+        }
+        if let Some(parent) = parent {
+            self.children.entry(parent).or_default().insert(id);
+        }
+    }
+
+    /// Subscribe to the changes matching a filter.
+    ///
+    /// The subscription receives the changes of all subsequent calls to
+    /// [`apply()`], independently of [`drain_changes()`].
+    ///
+    /// [`apply()`]: Truth::apply
+    /// [`drain_changes()`]: Truth::drain_changes
+    pub fn subscribe(&mut self, filter: SubscriptionFilter) -> Subscription {
+        let (sender, receiver) = mpsc::channel();
+        self.subscribers.push((filter, sender));
+        Subscription {
+            receiver: Mutex::new(receiver),
+        }
+    }
+
+    /// Send a change to the subscribers it matches, and remove the dropped
+    /// subscribers.
+    fn notify(&mut self, change: &TruthChanged) {
+        let object_types = &self.object_types;
+        self.subscribers.retain(|(filter, sender)| {
+            let diffs: Vec<_> = change
+                .diffs
+                .iter()
+                .filter_map(|entity_diff| {
+                    let object_type = object_types.get(&entity_diff.id).map(String::as_str);
+                    filter.filter(object_type, entity_diff)
+                })
+                .collect();
+            diffs.is_empty()
+                || sender
+                    .send(TruthChanged {
+                        version: change.version,
+                        diffs,
+                    })
+                    .is_ok()
+        });
+    }
+
+    /// Remove and return the change notifications recorded by [`apply()`].
+    ///
+    /// [`apply()`]: Truth::apply
+    pub fn drain_changes(&mut self) -> impl Iterator<Item = TruthChanged> + '_ {
+        self.changes.drain(..)
+    }
+
+    /// Capture a snapshot of all existing objects at the current version.
+    pub fn snapshot(&self) -> VersionedSnapshot {
+        let registry = self.world.resource::<AppTypeRegistry>().read();
+        let entities = self
+            .world
+            .iter_entities()
+            .filter(|entity| entity.contains::<Object>())
+            .map(|entity| entity.id());
+        VersionedSnapshot {
+            version: self.version,
+            snapshot: Snapshot::capture_entities(&self.world, entities, &registry),
+        }
+    }
+}
+
+/// System set of the [`apply_truth()`] system.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, SystemSet)]
+pub struct TruthSet;
+
+/// Plugin adding a [`Truth`] resource sharing the [`AppTypeRegistry`] of the
+/// app, and the system applying it.
+///
+/// The [`apply_truth()`] system runs in the [`TruthSet`] system set of the
+/// [`PostUpdate`] schedule, and sends the [`TruthChanged`] events extensions
+/// read to observe the changes.
+#[derive(Default)]
+pub struct TruthPlugin;
+
+impl Plugin for TruthPlugin {
+    fn build(&self, app: &mut App) {
+        let registry = app.world.resource::<AppTypeRegistry>().clone();
+        app.insert_resource(Truth::new(registry))
+            .add_event::<TruthChanged>()
+            .add_systems(PostUpdate, apply_truth.in_set(TruthSet));
+    }
+}
+
+/// Apply the pending mutations of the [`Truth`] resource, and send the
+/// resulting [`TruthChanged`] events.
+pub fn apply_truth(world: &mut World) {
+    world.resource_scope(|world, mut truth: Mut<Truth>| {
+        if let Err(err) = truth.apply() {
+            warn!("Failed to apply truth mutation: {}", err);
+        }
+        world.send_event_batch(truth.drain_changes());
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diff::DiffContent;
+    use bevy::app::Update;
+
+    #[derive(Debug, Default, Clone, PartialEq, Component, Reflect)]
+    #[reflect(Component, DiffCapture)]
+    struct Material {
+        roughness: f32,
+    }
+
+    fn truth() -> Truth {
+        let registry = AppTypeRegistry::default();
+        registry.write().register::<Material>();
+        Truth::new(registry)
+    }
+
+    #[test]
+    fn objects() {
+        let mut truth = truth();
+        let material = Material { roughness: 0.5 };
+        let root = truth.create(Object::new("Folder"), &[]).unwrap();
+        truth.apply().unwrap();
+        let child = truth
+            .create(Object::new("Material").with_parent(root), &[&material])
+            .unwrap();
+        truth.apply().unwrap();
+        assert_eq!(truth.version(), 2);
+        assert_eq!(truth.children(root), [child]);
+        assert_eq!(truth.object(child).unwrap().object_type, "Material");
+        assert_eq!(truth.get::<Material>(child), Some(&material));
+        assert_eq!(truth.objects().count(), 2);
+
+        let changes: Vec<_> = truth.drain_changes().collect();
+        assert_eq!(changes.len(), 2);
+        assert_eq!(changes[1].version, 2);
+        assert_eq!(changes[1].diffs.len(), 2);
+        assert!(changes[1]
+            .diffs
+            .iter()
+            .all(|entity_diff| entity_diff.id == child
+                && matches!(entity_diff.diff.content(), [DiffContent::Insert(_)])));
+
+        // Properties
+        truth.set::<Material>(child, ".roughness", &1_f32).unwrap();
+        truth.apply().unwrap();
+        assert_eq!(truth.get::<Material>(child).unwrap().roughness, 1.);
+        let snapshot = truth.snapshot();
+        assert_eq!(snapshot.version, 3);
+        assert_eq!(snapshot.snapshot.entities().count(), 2);
+        truth.history_mut().undo();
+        truth.apply().unwrap();
+        assert_eq!(truth.get::<Material>(child), Some(&material));
+        assert_eq!(truth.version(), 4);
+
+        // Parents
+        let orphan = EntityId::new();
+        assert!(matches!(
+            truth.create(Object::new("Material").with_parent(orphan), &[]),
+            Err(Error::InvalidParent { parent, .. }) if parent == orphan
+        ));
+        assert!(matches!(
+            truth.set_parent(root, Some(child)),
+            Err(Error::InvalidParent { object, parent }) if object == root && parent == child
+        ));
+        truth.set_parent(child, None).unwrap();
+        truth.apply().unwrap();
+        assert!(truth.children(root).is_empty());
+        truth.history_mut().undo();
+        truth.apply().unwrap();
+        assert_eq!(truth.children(root), [child]);
+
+        // Deleting an object deletes its descendants, and can be undone
+        let entity = truth.entity(child).unwrap();
+        truth.drain_changes().for_each(drop);
+        truth.delete(root).unwrap();
+        truth.apply().unwrap();
+        assert_eq!(truth.objects().count(), 0);
+        assert!(truth.entity(child).is_none());
+        assert!(matches!(
+            truth.set::<Material>(child, ".roughness", &0_f32),
+            Err(Error::UnmappedEntity(id)) if id == child
+        ));
+        let change = truth.drain_changes().next().unwrap();
+        assert_eq!(change.diffs.len(), 3);
+        truth.history_mut().undo();
+        truth.apply().unwrap();
+        assert_eq!(truth.entity(child), Some(entity));
+        assert_eq!(truth.get::<Material>(child), Some(&material));
+        assert_eq!(truth.children(root), [child]);
+    }
+
+    #[test]
+    fn subscription() {
+        let mut truth = truth();
+        let hierarchy =
+            truth.subscribe(SubscriptionFilter::new().with_field(FieldPath::of::<Object>("")));
+        let materials = truth.subscribe(SubscriptionFilter::new().with_object_type("Material"));
+        let root = truth.create(Object::new("Folder"), &[]).unwrap();
+        let child = truth
+            .create(Object::new("Material"), &[&Material::default()])
+            .unwrap();
+        truth.apply().unwrap();
+        let inspector = truth.subscribe(
+            SubscriptionFilter::new()
+                .with_object(child)
+                .with_field(FieldPath::of::<Material>(".roughness")),
+        );
+
+        // Only the insertion of the Object components is part of the hierarchy
+        let change = hierarchy.try_recv().unwrap();
+        assert_eq!(change.version, 1);
+        assert_eq!(change.diffs.len(), 2);
+        assert!(change.diffs.iter().all(|entity_diff| entity_diff
+            .diff
+            .target()
+            .unwrap()
+            .component()
+            == Some(Object::type_path())));
+        assert_eq!(materials.drain().len(), 1);
+
+        truth.set::<Material>(child, ".roughness", &1_f32).unwrap();
+        truth
+            .set::<Object>(root, ".object_type", &"Group".to_string())
+            .unwrap();
+        truth.apply().unwrap();
+        let change = inspector.try_recv().unwrap();
+        assert_eq!(change.version, 2);
+        assert_eq!(change.diffs.len(), 1);
+        assert_eq!(change.diffs[0].id, child);
+        assert_eq!(hierarchy.try_recv().unwrap().diffs[0].id, root);
+        assert_eq!(materials.try_recv().unwrap().diffs[0].id, child);
+
+        // The type of deleted objects is still matched
+        truth.delete(child).unwrap();
+        truth.apply().unwrap();
+        assert_eq!(truth.object_type(child), Some("Material"));
+        let change = materials.try_recv().unwrap();
+        assert_eq!(change.version, 3);
+        assert_eq!(change.diffs.len(), 2);
+        assert!(materials.try_recv().is_none());
+        assert_eq!(inspector.drain().len(), 1);
+        assert_eq!(hierarchy.drain().len(), 1);
+
+        // Dropped subscriptions are removed
+        drop(inspector);
+        drop(materials);
+        truth.history_mut().undo();
+        truth.apply().unwrap();
+        assert_eq!(truth.subscribers.len(), 1);
+        assert_eq!(hierarchy.drain().len(), 1);
+    }
+
+    #[test]
+    fn plugin() {
+        let mut app = App::new();
+        app.add_plugins(TruthPlugin).register_type::<Material>();
+        app.add_systems(Update, |mut truth: bevy::ecs::system::ResMut<Truth>| {
+            if truth.version() == 0 {
+                truth
+                    .create(Object::new("Material"), &[&Material::default()])
+                    .unwrap();
+            }
+        });
+        app.update();
+        let events: Vec<_> = app
+            .world
+            .resource_mut::<Events<TruthChanged>>()
+            .drain()
+            .collect();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].version, 1);
+        assert_eq!(app.world.resource::<Truth>().objects().count(), 1);
+    }
+}